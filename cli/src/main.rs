@@ -3,12 +3,13 @@ mod opts;
 use std::{
     io::{Read, Write},
     os::unix::net::UnixStream,
+    path::PathBuf,
 };
 
 use clap::Parser;
 use serde::Serialize;
 use serde_json::to_string;
-use wallpaper_manager_ipc::{socket_path, IpcError, IpcMessage, IpcResponse};
+use wallpaper_manager_ipc::{socket_path, IpcError, IpcMessage, IpcResponse, Transition};
 use wallpaper_manager_daemon::run;
 
 use crate::opts::{Opts, SubCmd};
@@ -17,19 +18,45 @@ fn main() {
     let args = Opts::parse();
 
     let msg = match args.subcmd {
-        SubCmd::StartDaemon { dir, interval, wallpaper_daemon } => {
-            run(dir, interval, wallpaper_daemon).unwrap();
+        SubCmd::StartDaemon {
+            dir,
+            interval,
+            wallpaper_daemon,
+            backend_options,
+            rotation,
+            preload_options,
+            scan_options,
+            collection_options,
+            resolution_options,
+        } => {
+            run(dir, interval, wallpaper_daemon, backend_options, rotation, preload_options, scan_options, collection_options, resolution_options).unwrap();
             std::process::exit(0);
         },
         SubCmd::StopDaemon {} => IpcMessage::StopDaemon {},
         SubCmd::PausePlay {} => IpcMessage::PausePlay {},
         SubCmd::ResumePlay {} => IpcMessage::ResumePlay {},
-        SubCmd::NextWallpaper {} => IpcMessage::NextWallpaper {},
-        SubCmd::PreviousWallpaper {} => IpcMessage::PreviousWallpaper {},
-        SubCmd::MoveWallpaperToIndex { path, index } => IpcMessage::MoveWallpaperToIndex { path, index },
-        SubCmd::GoToWallpaper { path } => IpcMessage::GoToWallpaper { path },
+        SubCmd::NextWallpaper { output } => IpcMessage::NextWallpaper { output },
+        SubCmd::PreviousWallpaper { output } => IpcMessage::PreviousWallpaper { output },
+        SubCmd::MoveWallpaperToIndex { path, index } => IpcMessage::MoveWallpaperToIndex { path: absolute(path), index },
+        SubCmd::GoToWallpaper { path, output } => IpcMessage::GoToWallpaper { path: absolute(path), output },
         SubCmd::AllWallpapers {} => IpcMessage::AllWallpapers {},
         SubCmd::CurrentInterval {} => IpcMessage::CurrentInterval {},
+        SubCmd::CurrentWallpapers {} => IpcMessage::CurrentWallpapers {},
+        SubCmd::LastError {} => IpcMessage::LastError {},
+        SubCmd::SetTransition { reset, transition } => {
+            IpcMessage::SetTransition { transition: Transition { reset, ..transition.into() } }
+        }
+        SubCmd::Status {} => IpcMessage::Status {},
+        SubCmd::SetFilters { include, exclude } => IpcMessage::SetFilters { include, exclude },
+        SubCmd::AddWallpaper { path, position } => IpcMessage::AddWallpaper { path: absolute(path), position },
+        SubCmd::RemoveWallpaper { path } => IpcMessage::RemoveWallpaper { path: absolute(path) },
+        SubCmd::AddDirectory { dir, recursive } => IpcMessage::AddDirectory { dir: absolute(dir), recursive },
+        SubCmd::RemoveDirectory { dir } => IpcMessage::RemoveDirectory { dir: absolute(dir) },
+        SubCmd::SavePlaylist { path } => IpcMessage::SavePlaylist { path: absolute(path) },
+        SubCmd::SwitchCollection { name } => IpcMessage::SwitchCollection { name },
+        SubCmd::InvalidWallpapers {} => IpcMessage::InvalidWallpapers {},
+        SubCmd::SetResolutionRules { rules } => IpcMessage::SetResolutionRules { rules: rules.into() },
+        SubCmd::ResolutionRules {} => IpcMessage::ResolutionRules {},
     };
 
     let mut conn = UnixStream::connect(socket_path().unwrap()).unwrap();
@@ -53,11 +80,79 @@ fn main() {
                 }
                 println!("{}", to_string(&Item { is_paused, interval, elapsed }).expect("wallpaper-managers to return a valid json"))
             },
-        },
-        Err(err) => match err {
-            IpcError::PathNotAdded { path } => {
-                eprintln!("Path '{}' not added to paths", path.display())
+            IpcResponse::CurrentWallpapers { entries } => {
+                println!("{}", to_string(&entries).expect("wallpaper-managers to return a valid json"))
+            },
+            IpcResponse::ResolutionRules { rules } => {
+                println!("{}", to_string(&rules).expect("wallpaper-managers to return a valid json"))
+            },
+            IpcResponse::InvalidWallpapers { entries } => {
+                println!("{}", to_string(&entries).expect("wallpaper-managers to return a valid json"))
+            },
+            IpcResponse::LastError { error } => {
+                if let Some(err) = error {
+                    print_error(err);
+                }
             },
+            IpcResponse::Status { backend, chain, reason, is_paused, wallpapers, collection, collections } => {
+                #[derive(Serialize)]
+                struct Item {
+                    backend: String,
+                    chain: Vec<String>,
+                    reason: Option<String>,
+                    is_paused: bool,
+                    wallpapers: usize,
+                    collection: String,
+                    collections: Vec<String>,
+                }
+                let item = Item { backend, chain, reason, is_paused, wallpapers, collection, collections };
+                println!("{}", to_string(&item).expect("wallpaper-managers to return a valid json"))
+            },
+        },
+        Err(err) => {
+            print_error(err);
+            std::process::exit(1);
         }
     }
 }
+
+fn print_error(err: IpcError) {
+    match err {
+        IpcError::PathNotAdded { path } => {
+            eprintln!("Path '{}' not added to paths", path.display())
+        },
+        IpcError::BackendFailed { backend, path, message } => {
+            eprintln!("{} failed to set '{}': {}", backend, path.display(), message)
+        },
+        IpcError::UnknownOutput { output } => {
+            eprintln!("Unknown output '{}'", output)
+        },
+        IpcError::Unsupported { backend, message } => {
+            eprintln!("{}: {}", backend, message)
+        },
+        IpcError::InvalidFilter { filter, message } => {
+            eprintln!("Invalid filter '{}': {}", filter, message)
+        },
+        IpcError::SourcesFailed { message } => {
+            eprintln!("Failed to read the wallpapers: {}", message)
+        },
+        IpcError::InvalidPath { path, message } => {
+            eprintln!("Can't use '{}': {}", path.display(), message)
+        },
+        IpcError::WriteFailed { path, message } => {
+            eprintln!("Failed to write '{}': {}", path.display(), message)
+        },
+        IpcError::UnknownCollection { name } => {
+            eprintln!("Unknown collection '{}'", name)
+        },
+        IpcError::InvalidRules { message } => {
+            eprintln!("Invalid resolution rules: {}", message)
+        },
+    }
+}
+
+/// `path` relative to the directory of the client rather than the daemon.
+fn absolute(path: PathBuf) -> PathBuf {
+    // Collecting the components drops the `.` ones, like the daemon does.
+    std::env::current_dir().map_or_else(|_| path.clone(), |dir| dir.join(&path).components().collect())
+}
@@ -2,7 +2,13 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
-use wallpaper_manager_daemon::wallpaper_manager::WallpaperDaemon;
+use wallpaper_manager_daemon::backend::{BackendOptions, TransitionOptions};
+use wallpaper_manager_daemon::collections::CollectionOptions;
+use wallpaper_manager_daemon::preload::PreloadOptions;
+use wallpaper_manager_daemon::resolution::ResolutionOptions;
+use wallpaper_manager_daemon::scan::ScanOptions;
+use wallpaper_manager_daemon::sources::Source;
+use wallpaper_manager_daemon::wallpaper_manager::{Rotation, WallpaperDaemon};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -11,17 +17,37 @@ pub struct Opts {
     pub subcmd: SubCmd,
 }
 
+// Parsed once per run, the size of `StartDaemon` doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(clap::Subcommand)]
 pub enum SubCmd {
     #[clap(visible_alias = "start")]
     #[command(arg_required_else_help = true)]
     StartDaemon {
-        #[clap(short, long, required = true)]
-        dir: PathBuf,
+        /// Directories, files, playlists (.m3u, .m3u8 or .txt with one path
+        /// per line) or glob patterns like "~/Pictures/**/*.png", optionally
+        /// weighted as "PATH@WEIGHT" to set their share of the
+        /// rotation
+        #[clap(short, long, visible_alias = "source", required = true, num_args = 1..)]
+        dir: Vec<Source>,
         #[clap(short, long, required = true)]
         interval: u64,
-        #[clap(short, long, required = true)]
-        wallpaper_daemon: WallpaperDaemon,
+        /// Backends in order of preference, e.g. "hyprpaper,swww". The next
+        /// one takes over when a backend fails
+        #[clap(short, long, required = true, value_delimiter = ',')]
+        wallpaper_daemon: Vec<WallpaperDaemon>,
+        #[command(flatten)]
+        backend_options: BackendOptions,
+        #[clap(short, long, value_enum, default_value_t = Rotation::Synchronized)]
+        rotation: Rotation,
+        #[command(flatten)]
+        preload_options: PreloadOptions,
+        #[command(flatten)]
+        scan_options: ScanOptions,
+        #[command(flatten)]
+        collection_options: CollectionOptions,
+        #[command(flatten)]
+        resolution_options: ResolutionOptions,
     },
     #[clap(visible_alias = "stop")]
     StopDaemon {},
@@ -30,9 +56,15 @@ pub enum SubCmd {
     #[clap(visible_alias = "resume")]
     ResumePlay {},
     #[clap(visible_alias = "next")]
-    NextWallpaper {},
+    NextWallpaper {
+        #[clap(short, long)]
+        output: Option<String>,
+    },
     #[clap(visible_alias = "previous")]
-    PreviousWallpaper {},
+    PreviousWallpaper {
+        #[clap(short, long)]
+        output: Option<String>,
+    },
     #[clap(visible_alias = "move")]
     #[command(arg_required_else_help = true)]
     MoveWallpaperToIndex {
@@ -46,9 +78,101 @@ pub enum SubCmd {
     GoToWallpaper {
         #[clap(short, long, required = true)]
         path: PathBuf,
+        #[clap(short, long)]
+        output: Option<String>,
     },
     #[clap(visible_alias = "get-all")]
     AllWallpapers {},
     #[clap(visible_alias = "get-interval")]
     CurrentInterval {},
+    #[clap(visible_alias = "get-current")]
+    CurrentWallpapers {},
+    #[clap(visible_alias = "get-error")]
+    LastError {},
+    #[clap(visible_alias = "transition")]
+    #[command(arg_required_else_help = true)]
+    SetTransition {
+        /// Go back to the swww default of every setting not given
+        #[clap(long)]
+        reset: bool,
+        #[command(flatten)]
+        transition: TransitionOptions,
+    },
+    /// Show the backend in use and why it was picked, whether the rotation
+    /// is paused, and the wallpapers and collections
+    Status {},
+    /// Replace the `--include` and `--exclude` filters of the daemon, and
+    /// collect the wallpapers again
+    #[clap(visible_alias = "filter")]
+    SetFilters {
+        /// Globs or "re:REGEX", see `start-daemon --help`
+        #[clap(long)]
+        include: Vec<String>,
+        #[clap(long)]
+        exclude: Vec<String>,
+    },
+    /// Add an image or video to the rotation
+    #[clap(visible_alias = "add")]
+    #[command(arg_required_else_help = true)]
+    AddWallpaper {
+        #[clap(short, long, required = true)]
+        path: PathBuf,
+        /// Position in the rotation, 0 to show it right away. Random if not
+        /// given
+        #[clap(long)]
+        position: Option<usize>,
+    },
+    /// Remove a wallpaper from the rotation. One from a directory is back
+    /// when the directory is read again, see `--exclude` to leave it out
+    #[clap(visible_alias = "remove")]
+    #[command(arg_required_else_help = true)]
+    RemoveWallpaper {
+        #[clap(short, long, required = true)]
+        path: PathBuf,
+    },
+    /// Add the wallpapers of a directory to the rotation
+    #[clap(visible_alias = "add-dir")]
+    #[command(arg_required_else_help = true)]
+    AddDirectory {
+        #[clap(short, long, required = true)]
+        dir: PathBuf,
+        /// Also add the wallpapers in subdirectories
+        #[clap(short, long)]
+        recursive: bool,
+    },
+    /// Remove a directory and the wallpapers under it from the rotation
+    #[clap(visible_alias = "remove-dir")]
+    #[command(arg_required_else_help = true)]
+    RemoveDirectory {
+        #[clap(short, long, required = true)]
+        dir: PathBuf,
+    },
+    /// Save the wallpapers in their current order as an M3U playlist, which
+    /// can be given to `start-daemon --dir`
+    #[clap(visible_alias = "save")]
+    #[command(arg_required_else_help = true)]
+    SavePlaylist {
+        #[clap(short, long, required = true)]
+        path: PathBuf,
+    },
+    /// Switch to another collection from `--collections`, or back to
+    /// "default", resuming where it was left
+    #[clap(visible_alias = "switch")]
+    #[command(arg_required_else_help = true)]
+    SwitchCollection {
+        #[clap(short, long, required = true)]
+        name: String,
+    },
+    /// List the files left out because they can't be shown, and why
+    #[clap(visible_alias = "get-invalid")]
+    InvalidWallpapers {},
+    /// Replace the resolution rules, the ones not given are lifted
+    #[clap(visible_alias = "set-rules")]
+    SetResolutionRules {
+        #[command(flatten)]
+        rules: ResolutionOptions,
+    },
+    /// Show the resolution rules wallpapers are picked by
+    #[clap(visible_alias = "get-rules")]
+    ResolutionRules {},
 }
@@ -0,0 +1,293 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+
+use super::{run_command, Backend, Capabilities, ScaleMode};
+
+/// A backend running user supplied commands, for tools without a native backend.
+pub struct CommandBackend {
+    set: Template,
+    preload: Option<Template>,
+    unload: Option<Template>,
+    mode: ScaleMode,
+    loaded: Vec<PathBuf>,
+}
+
+impl CommandBackend {
+    pub fn new(
+        set: &str,
+        preload: Option<&str>,
+        unload: Option<&str>,
+        mode: ScaleMode,
+    ) -> Result<Self> {
+        Ok(Self {
+            set: Template::parse(set)?,
+            preload: preload.map(Template::parse).transpose()?,
+            unload: unload.map(Template::parse).transpose()?,
+            mode,
+            loaded: Vec::new(),
+        })
+    }
+
+    fn run(&self, template: &Template, path: &Path, output: Option<&str>) -> Result<()> {
+        let args = template.render(&Variables {
+            path: path.as_os_str(),
+            output: OsStr::new(output.unwrap_or_default()),
+            mode: OsStr::new(self.mode.as_str()),
+        });
+        let (program, args) = args.split_first().ok_or_else(|| eyre!("Empty command"))?;
+
+        run_command(Command::new(program).args(args))?;
+        Ok(())
+    }
+}
+
+impl Backend for CommandBackend {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            preload: self.preload.is_some(),
+            per_output: self.set.uses(Placeholder::Output),
+            ..Default::default()
+        }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        self.run(&self.set, path, output)?;
+        println!("Wallpaper {}", path.display());
+        Ok(())
+    }
+
+    fn preload(&mut self, path: &Path) -> Result<()> {
+        if let Some(template) = &self.preload {
+            println!("Preload {}", path.display());
+            self.run(template, path, None)?;
+            self.loaded.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn unload(&mut self, path: &Path) -> Result<()> {
+        if let Some(template) = &self.unload {
+            println!("Unload {}", path.display());
+            self.run(template, path, None)?;
+        }
+        self.loaded.retain(|p| p != path);
+        Ok(())
+    }
+
+    fn list_loaded(&mut self) -> Result<Vec<PathBuf>> {
+        Ok(self.loaded.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    Path,
+    Output,
+    Mode,
+}
+
+#[derive(Debug)]
+enum Piece {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+struct Variables<'a> {
+    path: &'a OsStr,
+    output: &'a OsStr,
+    mode: &'a OsStr,
+}
+
+/// A command line split into arguments without going through a shell.
+///
+/// Arguments are separated by whitespace and may be quoted with `'` or `"`,
+/// a backslash escapes the next character outside single quotes. `{path}`,
+/// `{output}` and `{mode}` are replaced after splitting, quoted or not, so a
+/// substituted value always stays a single argument. `{{` and `}}` are
+/// literal braces.
+#[derive(Debug)]
+struct Template {
+    args: Vec<Vec<Piece>>,
+}
+
+impl Template {
+    fn parse(template: &str) -> Result<Self> {
+        let mut args = Vec::new();
+        let mut arg: Option<Vec<Piece>> = None;
+        let mut literal = String::new();
+        let mut quote: Option<char> = None;
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (Some('\''), '\\') => literal.push('\\'),
+                (None, '\'' | '"') => {
+                    arg.get_or_insert_with(Vec::new);
+                    quote = Some(c);
+                }
+                (None, c) if c.is_whitespace() => {
+                    if let Some(mut pieces) = arg.take() {
+                        flush(&mut pieces, &mut literal);
+                        args.push(pieces);
+                    }
+                }
+                (_, '\\') => match chars.next() {
+                    Some(next) => {
+                        arg.get_or_insert_with(Vec::new);
+                        literal.push(next);
+                    }
+                    None => bail!("Trailing backslash in command '{}'", template),
+                },
+                (_, '{') => {
+                    let pieces = arg.get_or_insert_with(Vec::new);
+                    if chars.as_str().starts_with('{') {
+                        chars.next();
+                        literal.push('{');
+                        continue;
+                    }
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| eyre!("Unclosed placeholder in command '{}'", template))?;
+                    let placeholder = match &rest[..end] {
+                        "path" => Placeholder::Path,
+                        "output" => Placeholder::Output,
+                        "mode" => Placeholder::Mode,
+                        name => bail!("Unknown placeholder '{{{}}}' in command '{}'", name, template),
+                    };
+                    flush(pieces, &mut literal);
+                    pieces.push(Piece::Placeholder(placeholder));
+                    chars = rest[end + 1..].chars();
+                }
+                (_, '}') => {
+                    if !chars.as_str().starts_with('}') {
+                        bail!("Unmatched '}}' in command '{}', use '}}}}' for a literal brace", template);
+                    }
+                    chars.next();
+                    arg.get_or_insert_with(Vec::new);
+                    literal.push('}');
+                }
+                (_, c) => {
+                    arg.get_or_insert_with(Vec::new);
+                    literal.push(c);
+                }
+            }
+        }
+
+        if quote.is_some() {
+            bail!("Unclosed quote in command '{}'", template);
+        }
+        if let Some(mut pieces) = arg.take() {
+            flush(&mut pieces, &mut literal);
+            args.push(pieces);
+        }
+        if args.is_empty() {
+            bail!("Empty command");
+        }
+
+        Ok(Self { args })
+    }
+
+    fn uses(&self, placeholder: Placeholder) -> bool {
+        self.args
+            .iter()
+            .flatten()
+            .any(|piece| matches!(piece, Piece::Placeholder(p) if *p == placeholder))
+    }
+
+    /// Substitute the placeholders. An argument made of a single placeholder
+    /// that expands to nothing is dropped instead of passed as `""`.
+    fn render(&self, variables: &Variables) -> Vec<OsString> {
+        self.args
+            .iter()
+            .filter_map(|pieces| {
+                let mut arg = OsString::new();
+                for piece in pieces {
+                    match piece {
+                        Piece::Literal(literal) => arg.push(literal),
+                        Piece::Placeholder(Placeholder::Path) => arg.push(variables.path),
+                        Piece::Placeholder(Placeholder::Output) => arg.push(variables.output),
+                        Piece::Placeholder(Placeholder::Mode) => arg.push(variables.mode),
+                    }
+                }
+                let only_placeholder = matches!(pieces.as_slice(), [Piece::Placeholder(_)]);
+                (!(only_placeholder && arg.is_empty())).then_some(arg)
+            })
+            .collect()
+    }
+}
+
+fn flush(pieces: &mut Vec<Piece>, literal: &mut String) {
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(std::mem::take(literal)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, path: &str, output: &str) -> Vec<String> {
+        let variables = Variables { path: OsStr::new(path), output: OsStr::new(output), mode: OsStr::new("fill") };
+        Template::parse(template)
+            .unwrap()
+            .render(&variables)
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn path_with_spaces_stays_one_argument() {
+        assert_eq!(render("feh --bg-{mode} {path}", "/a b/c d.png", ""), ["feh", "--bg-fill", "/a b/c d.png"]);
+    }
+
+    #[test]
+    fn placeholders_expand_inside_quotes() {
+        assert_eq!(render("set 'file={path}' \"{output}: {mode}\"", "/a b.png", "DP-1"), ["set", "file=/a b.png", "DP-1: fill"]);
+    }
+
+    #[test]
+    fn escapes_and_braces() {
+        assert_eq!(render(r#"echo \"x\" "a \"b\"" 'c\d' {{path}} \ "#, "/p", ""), ["echo", "\"x\"", "a \"b\"", "c\\d", "{path}", " "]);
+    }
+
+    #[test]
+    fn empty_output_is_dropped() {
+        assert_eq!(render("tool {output} {path}", "/p", ""), ["tool", "/p"]);
+        assert_eq!(render("tool {output} {path}", "/p", "DP-1"), ["tool", "DP-1", "/p"]);
+        // Only a lone placeholder is dropped, explicit quotes or text keep it.
+        assert_eq!(render("tool --output={output} {path}", "/p", ""), ["tool", "--output=", "/p"]);
+        assert_eq!(render("tool '' {path}", "/p", ""), ["tool", "", "/p"]);
+    }
+
+    #[test]
+    fn uses_output_only_when_it_appears() {
+        assert!(Template::parse("tool {output} {path}").unwrap().uses(Placeholder::Output));
+        assert!(!Template::parse("tool {path}").unwrap().uses(Placeholder::Output));
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        for (template, error) in [
+            (r"feh {path} \", "Trailing backslash"),
+            ("feh {file}", "Unknown placeholder '{file}'"),
+            ("feh {path", "Unclosed placeholder"),
+            ("feh path}", "Unmatched '}'"),
+            ("feh '{path}", "Unclosed quote"),
+            ("   ", "Empty command"),
+        ] {
+            let e = Template::parse(template).unwrap_err().to_string();
+            assert!(e.contains(error), "{}: {}", template, e);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+use wallpaper_manager_ipc::Transition;
+
+use super::{Backend, BackendOptions, Capabilities};
+use crate::outputs::OutputInfo;
+use crate::wallpaper_manager::WallpaperDaemon;
+
+/// An ordered list of backends acting as one.
+///
+/// Changes go to the active backend. When it fails the next ones are tried
+/// and the first that succeeds becomes active, after being given the
+/// wallpapers of the other outputs too. [`Backend::recover`] moves back to
+/// the primary backend once it works again.
+///
+/// Backends are only created when first needed, so a fallback for a
+/// compositor that isn't running doesn't stop the daemon from starting.
+pub struct Fallback {
+    options: BackendOptions,
+    slots: Vec<Slot>,
+    active: usize,
+    /// Wallpaper per output name, `*` being the one for all other outputs.
+    wallpapers: BTreeMap<String, PathBuf>,
+    /// The transition settings with the changes made over IPC, for the
+    /// backends created later.
+    transition: Transition,
+}
+
+struct Slot {
+    daemon: WallpaperDaemon,
+    backend: Option<Box<dyn Backend>>,
+}
+
+impl Fallback {
+    pub fn new(daemons: Vec<WallpaperDaemon>, options: BackendOptions) -> Result<Self> {
+        let slots = daemons.into_iter().map(|daemon| Slot { daemon, backend: None }).collect();
+        let transition = options.transition.clone().into();
+        let mut fallback = Self { options, slots, active: 0, wallpapers: BTreeMap::new(), transition };
+
+        let mut failures = Vec::new();
+        for index in 0..fallback.slots.len() {
+            match fallback.backend_at(index) {
+                Ok(_) => {
+                    fallback.active = index;
+                    return Ok(fallback);
+                }
+                Err(e) => {
+                    eprintln!("Failed to start the {} backend: {:?}", fallback.slots[index].daemon.name(), e);
+                    failures.push(format!("{}: {:#}", fallback.slots[index].daemon.name(), e));
+                }
+            }
+        }
+        bail!("No backend could be started, {}", failures.join("; "))
+    }
+
+    /// Names of the backends, in order of preference.
+    pub fn chain(&self) -> Vec<String> {
+        self.slots.iter().map(|slot| slot.daemon.name()).collect()
+    }
+
+    fn backend_at(&mut self, index: usize) -> Result<&mut Box<dyn Backend>> {
+        let slot = &mut self.slots[index];
+        if slot.backend.is_none() {
+            let mut backend = slot.daemon.backend(&self.options)?;
+            if backend.capabilities().transitions {
+                backend.set_transition(&Transition { reset: true, ..self.transition.clone() })?;
+            }
+            slot.backend = Some(backend);
+        }
+        Ok(slot.backend.as_mut().unwrap())
+    }
+
+    fn active(&mut self) -> &mut Box<dyn Backend> {
+        // The active backend is always created, see `new` and `switch`.
+        self.slots[self.active].backend.as_mut().unwrap()
+    }
+
+    /// Show every wallpaper but the one on `except` with the backend at
+    /// `index`, so it shows the same as the one it replaces.
+    fn replay(&mut self, index: usize, except: Option<&str>) -> Result<()> {
+        let wallpapers: Vec<(String, PathBuf)> = self
+            .wallpapers
+            .iter()
+            .filter(|(output, _)| Some(output.as_str()) != except)
+            .map(|(output, path)| (output.clone(), path.clone()))
+            .collect();
+        let backend = self.backend_at(index)?;
+        for (output, path) in wallpapers {
+            backend.set(&path, (output != "*").then_some(output.as_str()))?;
+        }
+        Ok(())
+    }
+
+    fn switch(&mut self, index: usize) {
+        println!(
+            "Switching from the {} backend to {}",
+            self.slots[self.active].daemon.name(),
+            self.slots[index].daemon.name()
+        );
+        self.active = index;
+    }
+}
+
+impl Backend for Fallback {
+    fn name(&self) -> &'static str {
+        self.slots[self.active].backend.as_ref().map_or("fallback", |backend| backend.name())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.slots[self.active].backend.as_ref().map(|backend| backend.capabilities()).unwrap_or_default()
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        let key = output.unwrap_or("*").to_string();
+        let len = self.slots.len();
+        let mut failures = Vec::new();
+
+        for index in (self.active..len).chain(0..self.active) {
+            let result = self.backend_at(index).and_then(|backend| backend.set(path, output));
+            match result {
+                Ok(()) => {
+                    if index != self.active {
+                        if let Err(e) = self.replay(index, Some(&key)) {
+                            eprintln!("Failed to restore the other outputs: {:?}", e);
+                        }
+                        self.switch(index);
+                    }
+                    if output.is_none() {
+                        self.wallpapers.clear();
+                    }
+                    self.wallpapers.insert(key, path.to_path_buf());
+                    return Ok(());
+                }
+                Err(e) => {
+                    let name = self.slots[index].daemon.name();
+                    eprintln!("The {} backend failed: {:?}", name, e);
+                    failures.push(format!("{}: {:#}", name, e));
+                }
+            }
+        }
+        Err(eyre!("Every backend failed, {}", failures.join("; ")))
+    }
+
+    fn preload(&mut self, path: &Path) -> Result<()> {
+        self.active().preload(path)
+    }
+
+    fn unload(&mut self, path: &Path) -> Result<()> {
+        self.active().unload(path)
+    }
+
+    fn list_loaded(&mut self) -> Result<Vec<PathBuf>> {
+        self.active().list_loaded()
+    }
+
+    fn set_transition(&mut self, transition: &Transition) -> Result<()> {
+        let mut supported = self.slots.iter().any(|slot| slot.daemon == WallpaperDaemon::Swww);
+        for backend in self.slots.iter_mut().filter_map(|slot| slot.backend.as_mut()) {
+            if backend.capabilities().transitions {
+                backend.set_transition(transition)?;
+                supported = true;
+            }
+        }
+        if !supported {
+            bail!("None of {} supports transitions", self.chain().join(", "));
+        }
+        self.transition.merge(transition);
+        Ok(())
+    }
+
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        self.active().outputs()
+    }
+
+    fn probe(&mut self) -> Result<()> {
+        self.active().probe()
+    }
+
+    fn recover(&mut self) -> Result<()> {
+        if self.active == 0 {
+            return Ok(());
+        }
+        // With no wallpaper to show again, the replay would prove nothing.
+        self.backend_at(0)?.probe()?;
+        self.replay(0, None)?;
+        self.switch(0);
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::Result;
+
+use super::{absolute, run_command, Backend, Capabilities, ScaleMode};
+
+const SCHEMA: &str = "org.gnome.desktop.background";
+
+/// GNOME, through the `org.gnome.desktop.background` gsettings keys.
+///
+/// GNOME shows one wallpaper on every monitor. Since GNOME 42 the dark style
+/// has its own `picture-uri-dark` key, both are set so the wallpaper doesn't
+/// change with the style.
+pub struct Gnome {
+    /// Whether the schema has `picture-uri-dark`.
+    dark_key: bool,
+}
+
+impl Gnome {
+    pub fn new(mode: ScaleMode) -> Result<Self> {
+        let keys = run_command(Command::new("gsettings").arg("list-keys").arg(SCHEMA))?;
+        let dark_key = String::from_utf8_lossy(&keys.stdout).lines().any(|key| key == "picture-uri-dark");
+
+        let options = match mode {
+            ScaleMode::Stretch => "stretched",
+            ScaleMode::Fill => "zoom",
+            ScaleMode::Fit => "scaled",
+            ScaleMode::Center => "centered",
+            ScaleMode::Tile => "wallpaper",
+        };
+        gsettings_set("picture-options", options)?;
+
+        Ok(Self { dark_key })
+    }
+}
+
+impl Backend for Gnome {
+    fn name(&self) -> &'static str {
+        "gnome"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn set(&mut self, path: &Path, _output: Option<&str>) -> Result<()> {
+        let uri = file_uri(path);
+        gsettings_set("picture-uri", &uri)?;
+        if self.dark_key {
+            gsettings_set("picture-uri-dark", &uri)?;
+        }
+        println!("Wallpaper {}", path.display());
+        Ok(())
+    }
+}
+
+fn gsettings_set(key: &str, value: &str) -> Result<()> {
+    run_command(Command::new("gsettings").arg("set").arg(SCHEMA).arg(key).arg(value))?;
+    Ok(())
+}
+
+/// A `file://` URI for `path`, percent-encoding everything but unreserved
+/// characters and slashes.
+pub(crate) fn file_uri(path: &Path) -> String {
+    let path = absolute(path);
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
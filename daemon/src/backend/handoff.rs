@@ -0,0 +1,72 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::Result;
+use wallpaper_manager_ipc::Transition;
+
+use super::{Backend, Capabilities, Mpvpaper};
+use crate::media::is_video;
+use crate::outputs::OutputInfo;
+
+/// A backend that can't play videos, handing them off to mpvpaper.
+///
+/// Videos play through [`Mpvpaper`], whose players are supervised across
+/// rotations, and everything else goes to the wrapped backend. Showing an
+/// image on an output stops the player covering it, so the image isn't
+/// hidden behind the video.
+pub struct Handoff {
+    backend: Box<dyn Backend>,
+    player: Mpvpaper,
+}
+
+impl Handoff {
+    pub fn new(backend: Box<dyn Backend>, player: Mpvpaper) -> Self {
+        Self { backend, player }
+    }
+}
+
+impl Backend for Handoff {
+    fn name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { video: true, ..self.backend.capabilities() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        if is_video(path) {
+            return self.player.set(path, output);
+        }
+        self.backend.set(path, output)?;
+        self.player.stop_on(output);
+        Ok(())
+    }
+
+    fn preload(&mut self, path: &Path) -> Result<()> {
+        self.backend.preload(path)
+    }
+
+    fn unload(&mut self, path: &Path) -> Result<()> {
+        self.backend.unload(path)
+    }
+
+    fn list_loaded(&mut self) -> Result<Vec<PathBuf>> {
+        self.backend.list_loaded()
+    }
+
+    fn set_transition(&mut self, transition: &Transition) -> Result<()> {
+        self.backend.set_transition(transition)
+    }
+
+    fn probe(&mut self) -> Result<()> {
+        self.backend.probe()
+    }
+
+    fn recover(&mut self) -> Result<()> {
+        self.backend.recover()
+    }
+
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        self.backend.outputs()
+    }
+}
@@ -0,0 +1,208 @@
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use color_eyre::Result;
+
+use super::{Backend, Capabilities};
+
+/// How long to wait for hyprpaper to answer a request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// <https://github.com/hyprwm/hyprpaper>, driven through its IPC socket.
+pub struct Hyprpaper {
+    client: HyprpaperClient,
+}
+
+impl Hyprpaper {
+    pub fn new() -> Result<Self> {
+        Ok(Self { client: HyprpaperClient::from_env()? })
+    }
+}
+
+impl Backend for Hyprpaper {
+    fn name(&self) -> &'static str {
+        "hyprpaper"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { preload: true, per_output: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        if let Err(e) = self.preload(path) {
+            eprintln!("Failed to preload wallpaper: {:?}", e);
+        }
+
+        println!("Wallpaper {}", path.display());
+        self.client.request_ok(&format!("wallpaper {},{}", output.unwrap_or_default(), utf8(path)?))
+    }
+
+    fn preload(&mut self, path: &Path) -> Result<()> {
+        println!("Preload {}", path.display());
+        self.client.request_ok(&format!("preload {}", utf8(path)?))
+    }
+
+    fn unload(&mut self, path: &Path) -> Result<()> {
+        println!("Unload {}", path.display());
+        self.client.request_ok(&format!("unload {}", utf8(path)?))
+    }
+
+    fn list_loaded(&mut self) -> Result<Vec<PathBuf>> {
+        Ok(parse_loaded(&self.client.request("listloaded")?))
+    }
+
+    fn probe(&mut self) -> Result<()> {
+        self.client.request("listloaded").map(|_| ())
+    }
+}
+
+/// The paths in a `listloaded` reply, one per line, or "no wallpapers
+/// loaded".
+fn parse_loaded(reply: &str) -> Vec<PathBuf> {
+    reply
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && *line != "no wallpapers loaded")
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// hyprpaper requests are plain text, so paths have to be valid UTF-8.
+fn utf8(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| eyre!("hyprpaper can't handle the non UTF-8 path {}", path.display()))
+}
+
+/// A client for hyprpaper's IPC socket, which takes one text request per
+/// connection and answers before closing it.
+pub struct HyprpaperClient {
+    socket: PathBuf,
+}
+
+impl HyprpaperClient {
+    pub fn new(socket: PathBuf) -> Self {
+        Self { socket }
+    }
+
+    /// Locate the socket of the Hyprland instance the daemon runs in.
+    pub fn from_env() -> Result<Self> {
+        let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE")
+            .context("HYPRLAND_INSTANCE_SIGNATURE is not set, is Hyprland running?")?;
+
+        let mut candidates = Vec::new();
+        if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+            candidates.push(PathBuf::from(runtime_dir).join("hypr").join(&signature).join(".hyprpaper.sock"));
+        }
+        // Hyprland before 0.40 kept its sockets in /tmp.
+        candidates.push(PathBuf::from("/tmp/hypr").join(&signature).join(".hyprpaper.sock"));
+
+        let socket = candidates
+            .iter()
+            .find(|candidate| candidate.exists())
+            .unwrap_or(&candidates[0])
+            .clone();
+        Ok(Self::new(socket))
+    }
+
+    /// Send `request` and return hyprpaper's reply.
+    pub fn request(&self, request: &str) -> Result<String> {
+        let mut stream = UnixStream::connect(&self.socket).with_context(|| {
+            format!("connecting to {}, is hyprpaper running?", self.socket.display())
+        })?;
+        stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        stream.set_write_timeout(Some(REPLY_TIMEOUT))?;
+
+        stream.write_all(request.as_bytes()).context("sending the request to hyprpaper")?;
+
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).context("reading the reply of hyprpaper")?;
+        Ok(reply)
+    }
+
+    /// Send a request that doesn't return data, any reply but "ok" is an error.
+    pub fn request_ok(&self, request: &str) -> Result<()> {
+        let reply = self.request(request)?;
+        let reply = reply.trim();
+        if reply != "ok" {
+            bail!("hyprpaper rejected '{}': {}", request, if reply.is_empty() { "no reply" } else { reply });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc;
+    use std::{fs, process, thread};
+
+    use super::*;
+
+    /// A hyprpaper answering each request with the next of `replies`, and
+    /// sending the requests it got.
+    fn fake_hyprpaper(name: &str, replies: &[&str]) -> (HyprpaperClient, mpsc::Receiver<String>) {
+        let dir = env::temp_dir().join(format!("wallpaper-manager-test-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join(".hyprpaper.sock");
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let replies: Vec<String> = replies.iter().map(|reply| reply.to_string()).collect();
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for reply in replies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).unwrap();
+                sender.send(String::from_utf8_lossy(&request[..read]).into_owned()).unwrap();
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        (HyprpaperClient::new(socket), requests)
+    }
+
+    #[test]
+    fn request_returns_the_reply() {
+        let (client, requests) = fake_hyprpaper("request", &["/a.png\n/b.png\n"]);
+        assert_eq!(client.request("listloaded").unwrap(), "/a.png\n/b.png\n");
+        assert_eq!(requests.recv().unwrap(), "listloaded");
+    }
+
+    #[test]
+    fn request_ok_fails_on_anything_but_ok() {
+        let (client, requests) = fake_hyprpaper("request-ok", &["ok\n", "wallpaper not preloaded", ""]);
+        client.request_ok("preload /a.png").unwrap();
+        let rejected = client.request_ok("wallpaper DP-1,/b.png").unwrap_err();
+        assert!(rejected.to_string().contains("wallpaper not preloaded"), "{}", rejected);
+        let silent = client.request_ok("unload /a.png").unwrap_err();
+        assert!(silent.to_string().contains("no reply"), "{}", silent);
+        let sent: Vec<String> = requests.iter().collect();
+        assert_eq!(sent, ["preload /a.png", "wallpaper DP-1,/b.png", "unload /a.png"]);
+    }
+
+    #[test]
+    fn set_preloads_first() {
+        let (client, requests) = fake_hyprpaper("set", &["ok", "ok"]);
+        let mut hyprpaper = Hyprpaper { client };
+        hyprpaper.set(Path::new("/a.png"), Some("DP-1")).unwrap();
+        let sent: Vec<String> = requests.iter().collect();
+        assert_eq!(sent, ["preload /a.png", "wallpaper DP-1,/a.png"]);
+    }
+
+    #[test]
+    fn list_loaded_understands_an_empty_list() {
+        let (client, _requests) = fake_hyprpaper("list-loaded", &["no wallpapers loaded", "/a.png\n/b c.png\n"]);
+        let mut hyprpaper = Hyprpaper { client };
+        assert!(hyprpaper.list_loaded().unwrap().is_empty());
+        assert_eq!(hyprpaper.list_loaded().unwrap(), [PathBuf::from("/a.png"), PathBuf::from("/b c.png")]);
+    }
+
+    #[test]
+    fn missing_socket_is_an_error() {
+        let client = HyprpaperClient::new(env::temp_dir().join("wallpaper-manager-test-missing.sock"));
+        assert!(client.request("listloaded").is_err());
+    }
+}
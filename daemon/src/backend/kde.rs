@@ -0,0 +1,80 @@
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use super::gnome::file_uri;
+use super::{run_command, Backend, Capabilities, ScaleMode};
+
+/// KDE Plasma, through the scripting API of plasmashell on D-Bus.
+///
+/// The scripting API knows screens by number rather than by name, so an
+/// output is matched to its desktop by position.
+pub struct Kde {
+    mode: ScaleMode,
+}
+
+impl Kde {
+    pub fn new(mode: ScaleMode) -> Self {
+        Self { mode }
+    }
+}
+
+impl Backend for Kde {
+    fn name(&self) -> &'static str {
+        "kde"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_output: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        let position = match output {
+            Some(name) => {
+                let outputs = self.outputs()?;
+                let info = outputs
+                    .iter()
+                    .find(|info| info.name == name)
+                    .ok_or_else(|| eyre!("Unknown output '{}'", name))?;
+                format!("{{ x: {}, y: {} }}", info.x, info.y)
+            }
+            None => "null".to_string(),
+        };
+
+        // The values of Qt's Image.fillMode.
+        let fill_mode = match self.mode {
+            ScaleMode::Stretch => 0,
+            ScaleMode::Fit => 1,
+            ScaleMode::Fill => 2,
+            ScaleMode::Tile => 3,
+            ScaleMode::Center => 6,
+        };
+
+        // serde_json quotes the URI as a valid JavaScript string literal.
+        let script = format!(
+            r#"const uri = {uri};
+const position = {position};
+desktops().forEach(desktop => {{
+    if (desktop.screen < 0) return;
+    const geometry = screenGeometry(desktop.screen);
+    if (position && (geometry.x != position.x || geometry.y != position.y)) return;
+    desktop.wallpaperPlugin = "org.kde.image";
+    desktop.currentConfigGroup = ["Wallpaper", "org.kde.image", "General"];
+    desktop.writeConfig("Image", uri);
+    desktop.writeConfig("FillMode", {fill_mode});
+}});"#,
+            uri = serde_json::to_string(&file_uri(path))?,
+        );
+
+        run_command(
+            Command::new("dbus-send")
+                .args(["--session", "--print-reply", "--dest=org.kde.plasmashell"])
+                .args(["/PlasmaShell", "org.kde.PlasmaShell.evaluateScript"])
+                .arg(format!("string:{}", script)),
+        )?;
+        println!("Wallpaper {}", path.display());
+        Ok(())
+    }
+}
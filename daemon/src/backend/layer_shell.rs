@@ -0,0 +1,388 @@
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use color_eyre::Result;
+use image::RgbaImage;
+use smithay_client_toolkit::{
+    compositor::{CompositorHandler, CompositorState},
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
+    output::{OutputHandler, OutputState},
+    reexports::{
+        calloop::{
+            channel::{self, Channel},
+            EventLoop,
+        },
+        calloop_wayland_source::WaylandSource,
+        client::{
+            globals::registry_queue_init,
+            protocol::{wl_output, wl_shm, wl_surface},
+            Connection, QueueHandle,
+        },
+    },
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    shell::{
+        wlr_layer::{
+            Anchor, KeyboardInteractivity, Layer, LayerShell as WlrLayerShell, LayerShellHandler,
+            LayerSurface, LayerSurfaceConfigure,
+        },
+        WaylandSurface,
+    },
+    shm::{slot::SlotPool, Shm, ShmHandler},
+};
+
+use super::{Backend, Capabilities, ScaleMode};
+use crate::render;
+
+/// How long `set` waits for the Wayland thread to draw the new wallpaper.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request to the Wayland thread, with where to send its outcome.
+enum Request {
+    Set(PathBuf, Option<String>, mpsc::Sender<Result<()>>),
+}
+
+/// Draws the wallpaper itself on a wlr-layer-shell background surface per
+/// output, so no external wallpaper daemon is needed.
+///
+/// The Wayland connection lives on its own thread with its own event loop,
+/// requests are sent to it over a channel. Each request carries its own
+/// reply channel, so a late reply to one that timed out isn't taken for the
+/// reply to the next.
+pub struct LayerShellBackend {
+    requests: channel::Sender<Request>,
+}
+
+impl LayerShellBackend {
+    pub fn new(mode: ScaleMode) -> Result<Self> {
+        let (requests, channel) = channel::channel();
+        let (started, reply) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("layer-shell".to_string())
+            .spawn(move || {
+                if let Err(e) = run(mode, channel, started.clone()) {
+                    let _ = started.send(Err(e));
+                }
+            })
+            .context("spawning the layer-shell thread")?;
+
+        wait_for(&reply).context("connecting to the Wayland compositor")?;
+        Ok(Self { requests })
+    }
+}
+
+fn wait_for(reply: &mpsc::Receiver<Result<()>>) -> Result<()> {
+    reply
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|e| eyre!("No reply from the layer-shell thread: {}", e))?
+}
+
+impl Backend for LayerShellBackend {
+    fn name(&self) -> &'static str {
+        "layer-shell"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_output: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        let (reply_sender, reply) = mpsc::channel();
+        self.requests
+            .send(Request::Set(path.to_path_buf(), output.map(str::to_string), reply_sender))
+            .map_err(|_| eyre!("The layer-shell thread has exited"))?;
+        wait_for(&reply)?;
+        println!("Wallpaper {}", path.display());
+        Ok(())
+    }
+}
+
+fn run(
+    mode: ScaleMode,
+    requests: Channel<Request>,
+    started: mpsc::Sender<Result<()>>,
+) -> Result<()> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, event_queue) = registry_queue_init(&conn)?;
+    let qh = event_queue.handle();
+
+    let mut event_loop = EventLoop::<State>::try_new()?;
+    WaylandSource::new(conn, event_queue)
+        .insert(event_loop.handle())
+        .map_err(|e| eyre!("inserting the Wayland source: {}", e.error))?;
+
+    let compositor = CompositorState::bind(&globals, &qh).context("wl_compositor is not available")?;
+    let layer_shell = WlrLayerShell::bind(&globals, &qh).context("wlr-layer-shell is not available")?;
+    let shm = Shm::bind(&globals, &qh).context("wl_shm is not available")?;
+    let pool = SlotPool::new(4096, &shm)?;
+
+    let mut state = State {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+        compositor,
+        layer_shell,
+        shm,
+        pool,
+        mode,
+        image: None,
+        surfaces: Vec::new(),
+    };
+
+    event_loop
+        .handle()
+        .insert_source(requests, |event, _, state| {
+            if let channel::Event::Msg(request) = event {
+                state.handle(request);
+            }
+        })
+        .map_err(|e| eyre!("inserting the request channel: {}", e.error))?;
+
+    let _ = started.send(Ok(()));
+
+    loop {
+        event_loop.dispatch(None, &mut state)?;
+    }
+}
+
+struct Surface {
+    output: wl_output::WlOutput,
+    layer: LayerSurface,
+    size: Option<(u32, u32)>,
+    image: Option<Arc<RgbaImage>>,
+}
+
+struct State {
+    registry_state: RegistryState,
+    output_state: OutputState,
+    compositor: CompositorState,
+    layer_shell: WlrLayerShell,
+    shm: Shm,
+    pool: SlotPool,
+    mode: ScaleMode,
+    /// The image for outputs that appear later.
+    image: Option<Arc<RgbaImage>>,
+    surfaces: Vec<Surface>,
+}
+
+impl State {
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::Set(path, output, reply) => {
+                let _ = reply.send(self.set(&path, output.as_deref()));
+            }
+        }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        let targets: Vec<usize> = match output {
+            None => (0..self.surfaces.len()).collect(),
+            Some(name) => (0..self.surfaces.len())
+                .filter(|&index| self.output_name(index).as_deref() == Some(name))
+                .collect(),
+        };
+        if let (Some(name), true) = (output, targets.is_empty()) {
+            bail!("Unknown output '{}'", name);
+        }
+
+        let image = image::open(path)
+            .with_context(|| format!("decoding {}", path.display()))?
+            .into_rgba8();
+        let image = Arc::new(image);
+        if output.is_none() {
+            self.image = Some(Arc::clone(&image));
+        }
+
+        for index in targets {
+            self.surfaces[index].image = Some(Arc::clone(&image));
+            self.draw(index)?;
+        }
+        Ok(())
+    }
+
+    fn output_name(&self, index: usize) -> Option<String> {
+        self.output_state.info(&self.surfaces[index].output)?.name
+    }
+
+    fn draw(&mut self, index: usize) -> Result<()> {
+        let surface = &self.surfaces[index];
+        let (Some(image), Some((width, height))) = (&surface.image, surface.size) else {
+            return Ok(());
+        };
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let scale = self
+            .output_state
+            .info(&surface.output)
+            .map_or(1, |info| info.scale_factor)
+            .max(1);
+        let width = width * scale as u32;
+        let height = height * scale as u32;
+
+        let canvas = render::compose(image, width, height, self.mode);
+        let (buffer, data) = self.pool.create_buffer(
+            width as i32,
+            height as i32,
+            width as i32 * 4,
+            wl_shm::Format::Xrgb8888,
+        )?;
+        render::write_xrgb8888(&canvas, data);
+
+        let wl_surface = surface.layer.wl_surface();
+        wl_surface.set_buffer_scale(scale);
+        wl_surface.damage_buffer(0, 0, width as i32, height as i32);
+        buffer.attach_to(wl_surface)?;
+        surface.layer.commit();
+        Ok(())
+    }
+}
+
+impl CompositorHandler for State {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        _new_factor: i32,
+    ) {
+        if let Some(index) = self.surfaces.iter().position(|s| s.layer.wl_surface() == surface) {
+            if let Err(e) = self.draw(index) {
+                eprintln!("Failed to redraw wallpaper: {:?}", e);
+            }
+        }
+    }
+
+    fn transform_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _new_transform: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _time: u32,
+    ) {
+    }
+
+    fn surface_enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _output: &wl_output::WlOutput,
+    ) {
+    }
+
+    fn surface_leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _output: &wl_output::WlOutput,
+    ) {
+    }
+}
+
+impl OutputHandler for State {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        let surface = self.compositor.create_surface(qh);
+        let layer = self.layer_shell.create_layer_surface(
+            qh,
+            surface,
+            Layer::Background,
+            Some("wallpaper"),
+            Some(&output),
+        );
+        layer.set_anchor(Anchor::all());
+        layer.set_exclusive_zone(-1);
+        layer.set_keyboard_interactivity(KeyboardInteractivity::None);
+        layer.set_size(0, 0);
+        layer.commit();
+
+        self.surfaces.push(Surface { output, layer, size: None, image: self.image.clone() });
+    }
+
+    fn update_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wl_output::WlOutput,
+    ) {
+    }
+
+    fn output_destroyed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        self.surfaces.retain(|surface| surface.output != output);
+    }
+}
+
+impl LayerShellHandler for State {
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        self.surfaces.retain(|surface| &surface.layer != layer);
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        let Some(index) = self.surfaces.iter().position(|s| &s.layer == layer) else {
+            return;
+        };
+        let size = configure.new_size;
+        if self.surfaces[index].size == Some(size) {
+            return;
+        }
+        self.surfaces[index].size = Some(size);
+
+        if let Err(e) = self.draw(index) {
+            eprintln!("Failed to draw wallpaper: {:?}", e);
+        }
+    }
+}
+
+impl ShmHandler for State {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl ProvidesRegistryState for State {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
+
+delegate_compositor!(State);
+delegate_output!(State);
+delegate_shm!(State);
+delegate_layer!(State);
+delegate_registry!(State);
@@ -0,0 +1,191 @@
+//! Wallpaper backends.
+//!
+//! A backend is whatever actually puts an image on the screen, usually an
+//! external wallpaper daemon. `WallpaperManager` only talks to the [`Backend`]
+//! trait, so adding a new one doesn't require touching the rotation logic.
+
+mod command;
+mod fallback;
+mod gnome;
+mod handoff;
+mod hyprpaper;
+mod kde;
+mod layer_shell;
+mod mpvpaper;
+mod swaybg;
+mod swww;
+mod x11;
+mod xfce;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+use wallpaper_manager_ipc::Transition;
+
+use crate::media::MediaFallback;
+use crate::outputs::{session_outputs, OutputInfo};
+
+pub use command::CommandBackend;
+pub use fallback::Fallback;
+pub use gnome::Gnome;
+pub use handoff::Handoff;
+pub use hyprpaper::{Hyprpaper, HyprpaperClient};
+pub use kde::Kde;
+pub use layer_shell::LayerShellBackend;
+pub use mpvpaper::Mpvpaper;
+pub use swaybg::Swaybg;
+pub use swww::{wait_for_daemon, Swww, TransitionOptions};
+pub use x11::X11;
+pub use xfce::Xfce;
+
+/// Backend settings given to `start-daemon`.
+#[derive(clap::Args, Clone, Debug)]
+// Keep this doc comment from becoming the about text of `start-daemon`.
+#[command(about = None, long_about = None)]
+pub struct BackendOptions {
+    /// How the image is fitted to the output, for backends that support it
+    #[clap(short, long, value_enum, default_value_t = ScaleMode::Fill)]
+    pub mode: ScaleMode,
+    /// Command setting the wallpaper for `--wallpaper-daemon command`,
+    /// e.g. "feh --bg-fill {path}". Supports {path}, {output} and {mode}, the
+    /// `--mode` name
+    #[clap(long)]
+    pub set_command: Option<String>,
+    /// Command preloading a wallpaper for `--wallpaper-daemon command`
+    #[clap(long)]
+    pub preload_command: Option<String>,
+    /// Command unloading a preloaded wallpaper for `--wallpaper-daemon command`
+    #[clap(long)]
+    pub unload_command: Option<String>,
+    /// Start swww-daemon if it isn't running when a wallpaper is set
+    #[clap(long)]
+    pub start_swww_daemon: bool,
+    /// mpv options for `--wallpaper-daemon mpvpaper`, and for the videos
+    /// handed off to it
+    #[clap(long, default_value = "no-audio loop")]
+    pub mpv_options: String,
+    /// What to do with animated images and videos the backend can't show.
+    /// Videos are handed off to mpvpaper instead when it is installed on
+    /// Wayland
+    #[clap(long, value_enum, default_value_t = MediaFallback::Still)]
+    pub media_fallback: MediaFallback,
+    /// Seconds between attempts to go back to the first backend after it
+    /// failed, when several are given
+    #[clap(long, default_value_t = 60)]
+    pub fallback_probe_interval: u64,
+    #[command(flatten)]
+    pub transition: TransitionOptions,
+}
+
+/// How an image is fitted to an output whose size differs from it.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleMode {
+    /// Scale to the output size, ignoring the aspect ratio
+    Stretch,
+    /// Scale to cover the whole output, cropping what doesn't fit
+    Fill,
+    /// Scale to fit inside the output, leaving bars around it
+    Fit,
+    /// Don't scale, center the image
+    Center,
+    /// Don't scale, repeat the image
+    Tile,
+}
+
+impl ScaleMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScaleMode::Stretch => "stretch",
+            ScaleMode::Fill => "fill",
+            ScaleMode::Fit => "fit",
+            ScaleMode::Center => "center",
+            ScaleMode::Tile => "tile",
+        }
+    }
+}
+
+/// Optional features of a backend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Capabilities {
+    /// Images can be loaded ahead of time and must be unloaded afterwards.
+    pub preload: bool,
+    /// Each output can show a different wallpaper.
+    pub per_output: bool,
+    /// Changes can be animated, see [`Backend::set_transition`].
+    pub transitions: bool,
+    /// Animated GIF and WebP images play.
+    pub animated: bool,
+    /// Videos play.
+    pub video: bool,
+}
+
+pub trait Backend: Send {
+    /// Short name used in logs.
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Show `path` on `output`, or on every output if it is `None`.
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()>;
+
+    /// Load `path` so that a later `set` is instant.
+    fn preload(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Free a previously preloaded `path`.
+    fn unload(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Paths currently held in memory by the backend.
+    fn list_loaded(&mut self) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+
+    /// Update the transition used by the following changes.
+    fn set_transition(&mut self, _transition: &Transition) -> Result<()> {
+        bail!("{} does not support transitions", self.name())
+    }
+
+    /// Check that wallpapers can be shown right now, without changing them.
+    /// By default, that the outputs can be listed.
+    fn probe(&mut self) -> Result<()> {
+        self.outputs().map(|_| ())
+    }
+
+    /// Go back to the preferred backend if this one stands in for it, see
+    /// [`Fallback`].
+    fn recover(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Outputs the wallpaper can be shown on.
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        session_outputs()
+    }
+}
+
+/// Resolve a relative `path` against the current directory, for backends
+/// handing it to a process that doesn't share it.
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Run `command` to completion, failing on a non-zero exit status.
+pub(crate) fn run_command(command: &mut Command) -> Result<Output> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| eyre!("Failed to execute '{}': {}", program, e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("'{}' exited with {}: {}", program, output.status, stderr.trim());
+    }
+    Ok(output)
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use super::swaybg::{stop, wait_until_up};
+use super::{Backend, Capabilities, ScaleMode};
+
+/// <https://github.com/GhostNaN/mpvpaper>, which plays videos, animated
+/// images and still images through mpv.
+///
+/// mpvpaper plays a single file on a single output (or `*` for all of them),
+/// so there is one player per output. Players that died, e.g. because mpv
+/// crashed, are restarted on the next change, and a new player replaces the
+/// old one only once it is running.
+pub struct Mpvpaper {
+    /// Options handed to mpv through `mpvpaper -o`.
+    options: String,
+    players: BTreeMap<String, Player>,
+}
+
+struct Player {
+    child: Child,
+    path: PathBuf,
+}
+
+impl Mpvpaper {
+    pub fn new(mpv_options: &str, mode: ScaleMode) -> Self {
+        let scaling = match mode {
+            ScaleMode::Stretch => "keepaspect=no",
+            ScaleMode::Fill => "panscan=1.0",
+            ScaleMode::Center => "video-unscaled=yes",
+            // mpv can't tile, it fits instead.
+            ScaleMode::Fit | ScaleMode::Tile => "",
+        };
+        let options = [mpv_options, scaling]
+            .iter()
+            .filter(|option| !option.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self { options, players: BTreeMap::new() }
+    }
+
+    /// Restart the players that exited since the last change.
+    fn supervise(&mut self) {
+        for (output, player) in self.players.iter_mut() {
+            let Ok(Some(status)) = player.child.try_wait() else {
+                continue;
+            };
+            eprintln!("mpvpaper on {} exited with {}, restarting it", output, status);
+            match spawn(&self.options, output, &player.path) {
+                Ok(child) => player.child = child,
+                Err(e) => eprintln!("Failed to restart mpvpaper on {}: {:?}", output, e),
+            }
+        }
+    }
+
+    /// Stop the players covering `output`, all of them if it is `None`, so
+    /// that another backend's wallpaper shows there.
+    pub(crate) fn stop_on(&mut self, output: Option<&str>) {
+        let stopped: Vec<String> = self
+            .players
+            .keys()
+            .filter(|key| output.map_or(true, |output| key.as_str() == output || key.as_str() == "*"))
+            .cloned()
+            .collect();
+        for key in stopped {
+            if let Some(mut player) = self.players.remove(&key) {
+                stop(&mut player.child);
+            }
+        }
+    }
+}
+
+impl Backend for Mpvpaper {
+    fn name(&self) -> &'static str {
+        "mpvpaper"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_output: true, animated: true, video: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        self.supervise();
+
+        let key = output.unwrap_or("*").to_string();
+        let child = spawn(&self.options, &key, path)?;
+        println!("Wallpaper {}", path.display());
+
+        // A player for all outputs replaces the per output ones as well, and
+        // one for a single output replaces the one for all of them, which
+        // would otherwise keep playing over it.
+        let previous = match output {
+            None => std::mem::take(&mut self.players),
+            Some(_) => [key.as_str(), "*"].into_iter().filter_map(|key| self.players.remove_entry(key)).collect(),
+        };
+        self.players.insert(key, Player { child, path: path.to_path_buf() });
+        for (_, mut player) in previous {
+            stop(&mut player.child);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Mpvpaper {
+    fn drop(&mut self) {
+        for player in self.players.values_mut() {
+            stop(&mut player.child);
+        }
+    }
+}
+
+fn spawn(options: &str, output: &str, path: &Path) -> Result<Child> {
+    let mut command = Command::new("mpvpaper");
+    if !options.is_empty() {
+        command.arg("-o").arg(options);
+    }
+    let mut child = command
+        .arg(output)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| eyre!("Failed to execute 'mpvpaper': {:?}", e))?;
+    wait_until_up(&mut child)?;
+    Ok(child)
+}
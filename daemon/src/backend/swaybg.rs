@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::bail;
+use color_eyre::Result;
+
+use super::{Backend, Capabilities, ScaleMode};
+
+/// How long a new swaybg has to stay alive before it is considered up.
+const STARTUP_GRACE: Duration = Duration::from_millis(300);
+
+/// <https://github.com/swaywm/swaybg>
+///
+/// swaybg has no IPC, every change spawns a new process. The previous one is
+/// killed only once the new one is running, so the screen never goes blank.
+/// A single process draws every output, so it is given the wallpapers of all
+/// of them each time.
+pub struct Swaybg {
+    mode: ScaleMode,
+    child: Option<Child>,
+    /// Wallpaper per output name, `*` being the one for all other outputs.
+    wallpapers: BTreeMap<String, PathBuf>,
+}
+
+impl Swaybg {
+    pub fn new(mode: ScaleMode) -> Self {
+        Self { mode, child: None, wallpapers: BTreeMap::new() }
+    }
+}
+
+impl Backend for Swaybg {
+    fn name(&self) -> &'static str {
+        "swaybg"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_output: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        let mut wallpapers = self.wallpapers.clone();
+        if output.is_none() {
+            wallpapers.clear();
+        }
+        wallpapers.insert(output.unwrap_or("*").to_string(), path.to_path_buf());
+
+        let mut command = Command::new("swaybg");
+        for (output, path) in &wallpapers {
+            command.arg("-o").arg(output).arg("-i").arg(path).arg("-m").arg(self.mode.as_str());
+        }
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| color_eyre::eyre::eyre!("Failed to execute 'swaybg': {:?}", e))?;
+
+        wait_until_up(&mut child)?;
+        println!("Wallpaper {}", path.display());
+        self.wallpapers = wallpapers;
+
+        if let Some(mut previous) = self.child.replace(child) {
+            stop(&mut previous);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Swaybg {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            stop(&mut child);
+        }
+    }
+}
+
+/// Wait for `child` to survive its startup, failing if it exits before that.
+pub(crate) fn wait_until_up(child: &mut Child) -> Result<()> {
+    let started = Instant::now();
+    while started.elapsed() < STARTUP_GRACE {
+        if let Some(status) = child.try_wait()? {
+            bail!("process exited during startup with {}", status);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+pub(crate) fn stop(child: &mut Child) {
+    if let Err(e) = child.kill() {
+        eprintln!("Failed to kill process {}: {:?}", child.id(), e);
+    }
+    let _ = child.wait();
+}
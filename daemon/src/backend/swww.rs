@@ -0,0 +1,329 @@
+use std::env;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::builder::PossibleValuesParser;
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+use wallpaper_manager_ipc::Transition;
+
+use super::swaybg::stop;
+use super::{run_command, Backend, Capabilities};
+use crate::outputs::{session_outputs, OutputInfo};
+use crate::shuffle::random_index;
+
+/// How long to wait for swww-daemon to come up before giving up on a change.
+const DAEMON_WAIT: Duration = Duration::from_secs(2);
+
+/// Transition types understood by `swww img --transition-type`.
+const TRANSITION_TYPES: [&str; 13] = [
+    "none", "simple", "fade", "left", "right", "top", "bottom", "wipe", "wave", "grow", "center",
+    "any", "outer",
+];
+
+/// Picked by `random` mode, which never repeats the previous effect.
+const RANDOM_TRANSITION_TYPES: [&str; 11] = [
+    "simple", "fade", "left", "right", "top", "bottom", "wipe", "wave", "grow", "center", "outer",
+];
+
+/// Transition settings for the swww backend
+#[derive(clap::Args, Clone, Debug, Default)]
+#[command(about = None, long_about = None)]
+pub struct TransitionOptions {
+    /// swww transition type, or "random" for a different one on every change
+    #[clap(long, value_parser = PossibleValuesParser::new(TRANSITION_TYPES.iter().chain(&["random"])))]
+    pub transition_type: Option<String>,
+    /// How fast the transition approaches the new image, 1-255
+    #[clap(long)]
+    pub transition_step: Option<u8>,
+    /// Frame rate of the transition
+    #[clap(long)]
+    pub transition_fps: Option<u16>,
+    /// Duration of the transition in seconds
+    #[clap(long)]
+    pub transition_duration: Option<f32>,
+    /// Angle in degrees of the wipe and wave transitions
+    #[clap(long)]
+    pub transition_angle: Option<f32>,
+    /// Position of the grow and outer transitions, e.g. "center" or "0.5,0.5"
+    #[clap(long)]
+    pub transition_pos: Option<String>,
+}
+
+impl From<TransitionOptions> for Transition {
+    fn from(options: TransitionOptions) -> Self {
+        Transition {
+            reset: false,
+            kind: options.transition_type,
+            step: options.transition_step,
+            fps: options.transition_fps,
+            duration: options.transition_duration,
+            angle: options.transition_angle,
+            position: options.transition_pos,
+        }
+    }
+}
+
+/// <https://github.com/LGFae/swww>
+///
+/// The socket of swww-daemon is only used to tell whether it runs. Its
+/// messages are a binary format that changes between swww releases without
+/// a version check, so images go through `swww img` and the outputs and what
+/// they show through `swww query`, from the swww release matching the
+/// daemon. A swww-daemon started by `--start-swww-daemon` is stopped with the
+/// backend.
+pub struct Swww {
+    transition: Transition,
+    last_random: Option<usize>,
+    /// Start swww-daemon when it isn't running.
+    start_daemon: bool,
+    /// The swww-daemon started by the backend.
+    daemon: Option<Child>,
+}
+
+impl Swww {
+    pub fn new(transition: Transition, start_daemon: bool) -> Result<Self> {
+        let mut swww = Self { transition: Transition::default(), last_random: None, start_daemon, daemon: None };
+        swww.set_transition(&transition)?;
+        Ok(swww)
+    }
+
+    /// Make sure swww-daemon is reachable, waiting for it or starting it.
+    fn ensure_daemon(&mut self) -> Result<()> {
+        if wait_for_daemon(Duration::ZERO) {
+            return Ok(());
+        }
+
+        let started_by_us = self.daemon.as_mut().is_some_and(|child| matches!(child.try_wait(), Ok(None)));
+        if self.start_daemon && !started_by_us {
+            println!("swww-daemon is not running, starting it");
+            if let Some(mut exited) = self.daemon.take() {
+                stop(&mut exited);
+            }
+            let child = Command::new("swww-daemon")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .spawn()
+                .map_err(|e| eyre!("Failed to execute 'swww-daemon': {}", e))?;
+            self.daemon = Some(child);
+        }
+
+        if !wait_for_daemon(DAEMON_WAIT) {
+            bail!("swww-daemon is not running");
+        }
+        Ok(())
+    }
+
+    /// The transition type for the next change, resolving `random`.
+    fn transition_type(&mut self) -> Option<&'static str> {
+        match self.transition.kind.as_deref()? {
+            "random" => {
+                let mut index = random_index(RANDOM_TRANSITION_TYPES.len());
+                if Some(index) == self.last_random {
+                    index = (index + 1) % RANDOM_TRANSITION_TYPES.len();
+                }
+                self.last_random = Some(index);
+                Some(RANDOM_TRANSITION_TYPES[index])
+            }
+            kind => TRANSITION_TYPES.iter().copied().find(|t| *t == kind),
+        }
+    }
+}
+
+impl Backend for Swww {
+    fn name(&self) -> &'static str {
+        "swww"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_output: true, transitions: true, animated: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        self.ensure_daemon()?;
+
+        let mut command = Command::new("swww");
+        command.arg("img");
+        if let Some(output) = output {
+            command.arg("--outputs").arg(output);
+        }
+        if let Some(kind) = self.transition_type() {
+            command.arg("--transition-type").arg(kind);
+        }
+        let transition = &self.transition;
+        if let Some(step) = transition.step {
+            command.arg("--transition-step").arg(step.to_string());
+        }
+        if let Some(fps) = transition.fps {
+            command.arg("--transition-fps").arg(fps.to_string());
+        }
+        if let Some(duration) = transition.duration {
+            command.arg("--transition-duration").arg(duration.to_string());
+        }
+        if let Some(angle) = transition.angle {
+            command.arg("--transition-angle").arg(angle.to_string());
+        }
+        if let Some(position) = &transition.position {
+            command.arg("--transition-pos").arg(position);
+        }
+        run_command(command.arg(path))?;
+        println!("Wallpaper {}", path.display());
+        Ok(())
+    }
+
+    fn probe(&mut self) -> Result<()> {
+        self.ensure_daemon()
+    }
+
+    fn list_loaded(&mut self) -> Result<Vec<PathBuf>> {
+        self.ensure_daemon()?;
+        Ok(query()?.into_iter().filter_map(|output| output.image).collect())
+    }
+
+    /// The outputs of swww-daemon, placed where the session has them. swww
+    /// doesn't know the positions, nor the logical sizes of scaled outputs.
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        self.ensure_daemon()?;
+        let session = session_outputs().unwrap_or_else(|e| {
+            eprintln!("Unable to place the swww outputs: {:?}", e);
+            Vec::new()
+        });
+        Ok(query()?
+            .into_iter()
+            .map(|output| {
+                session.iter().find(|known| known.name == output.name).cloned().unwrap_or(OutputInfo {
+                    name: output.name,
+                    x: 0,
+                    y: 0,
+                    width: output.width,
+                    height: output.height,
+                })
+            })
+            .collect())
+    }
+
+    fn set_transition(&mut self, transition: &Transition) -> Result<()> {
+        if let Some(kind) = &transition.kind {
+            if kind != "random" && !TRANSITION_TYPES.contains(&kind.as_str()) {
+                bail!("Unknown transition type '{}', expected one of {} or random", kind, TRANSITION_TYPES.join(", "));
+            }
+        }
+        if transition.step == Some(0) {
+            bail!("The transition step must be between 1 and 255");
+        }
+        if transition.duration.is_some_and(|duration| duration.is_nan() || duration < 0.0) {
+            bail!("The transition duration must not be negative");
+        }
+
+        self.transition.merge(transition);
+        Ok(())
+    }
+}
+
+impl Drop for Swww {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.daemon.take() {
+            stop(&mut child);
+        }
+    }
+}
+
+/// Sockets swww-daemon may listen on, newest naming scheme first.
+fn socket_candidates() -> Vec<PathBuf> {
+    let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) else {
+        return vec![PathBuf::from("/tmp/swww/swww.socket")];
+    };
+    let display = env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_string());
+    vec![
+        runtime_dir.join(format!("swww-{}.socket", display)),
+        runtime_dir.join("swww.socket"),
+    ]
+}
+
+/// Whether a swww-daemon accepts connections, polling for up to `timeout`.
+pub fn wait_for_daemon(timeout: Duration) -> bool {
+    let started = Instant::now();
+    loop {
+        if socket_candidates().iter().any(|socket| UnixStream::connect(socket).is_ok()) {
+            return true;
+        }
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// An output as reported by `swww query`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SwwwOutput {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// `None` when the output shows a plain color.
+    pub image: Option<PathBuf>,
+}
+
+/// List the outputs known to swww-daemon and what they display.
+pub fn query() -> Result<Vec<SwwwOutput>> {
+    let output = run_command(Command::new("swww").arg("query"))?;
+    Ok(String::from_utf8_lossy(&output.stdout).lines().filter_map(parse_query_line).collect())
+}
+
+/// Parse a line like
+/// `eDP-1: 1920x1080, scale: 1, currently displaying: image: /path/to.png`.
+fn parse_query_line(line: &str) -> Option<SwwwOutput> {
+    // Some versions prefix each line with the namespace, e.g. `: eDP-1: ...`.
+    let line = line.trim().trim_start_matches(':').trim_start();
+    let (name, rest) = line.split_once(": ")?;
+    let (size, _) = rest.split_once(',').unwrap_or((rest, ""));
+    let (width, height) = size.trim().split_once('x')?;
+    let image = rest
+        .split_once("image: ")
+        .map(|(_, image)| PathBuf::from(image.trim()));
+
+    Some(SwwwOutput {
+        name: name.to_string(),
+        width: width.parse().ok()?,
+        height: height.parse().ok()?,
+        image,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_lines() {
+        assert_eq!(
+            parse_query_line("eDP-1: 1920x1080, scale: 1, currently displaying: image: /home/me/a b.png"),
+            Some(SwwwOutput {
+                name: "eDP-1".to_string(),
+                width: 1920,
+                height: 1080,
+                image: Some(PathBuf::from("/home/me/a b.png")),
+            })
+        );
+        assert_eq!(
+            parse_query_line(": DP-2: 2560x1440, scale: 2, currently displaying: image: /w.gif"),
+            Some(SwwwOutput { name: "DP-2".to_string(), width: 2560, height: 1440, image: Some(PathBuf::from("/w.gif")) })
+        );
+    }
+
+    #[test]
+    fn colors_have_no_image() {
+        let output = parse_query_line("HDMI-A-1: 1280x1024, scale: 1, currently displaying: color: 000000").unwrap();
+        assert_eq!((output.name.as_str(), output.image), ("HDMI-A-1", None));
+    }
+
+    #[test]
+    fn other_lines_are_ignored() {
+        assert_eq!(parse_query_line(""), None);
+        assert_eq!(parse_query_line("Error: failed to connect to the socket"), None);
+        assert_eq!(parse_query_line("eDP-1: unknown size"), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::Result;
+use image::{imageops, Rgba, RgbaImage};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xproto::{
+    AtomEnum, ChangeWindowAttributesAux, CloseDown, ConnectionExt as _, CreateGCAux, ImageFormat,
+    ImageOrder, PropMode,
+};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+use super::{Backend, Capabilities, ScaleMode};
+use crate::outputs::{randr_monitors, x11_outputs, OutputInfo};
+use crate::render;
+
+/// Draws the wallpaper on the root window of an X11 screen, for window
+/// managers like i3 or bspwm.
+///
+/// The pixmap is kept alive after disconnecting and published in
+/// `_XROOTPMAP_ID` and `ESETROOT_PMAP_ID`, the way `feh` and `hsetroot` do, so
+/// compositors and transparent terminals can find it. The pixmap of the
+/// previous wallpaper, ours or another setter's, is freed on each change.
+pub struct X11 {
+    mode: ScaleMode,
+    /// Wallpaper per monitor name, `*` being the one for all other monitors.
+    wallpapers: BTreeMap<String, PathBuf>,
+    /// Decoded images of `wallpapers`, so changing one monitor doesn't
+    /// decode the others again.
+    images: HashMap<PathBuf, RgbaImage>,
+}
+
+impl X11 {
+    pub fn new(mode: ScaleMode) -> Result<Self> {
+        // Fail early when there is no X server rather than on the first change.
+        connect()?;
+        Ok(Self { mode, wallpapers: BTreeMap::new(), images: HashMap::new() })
+    }
+
+    fn draw(&mut self, wallpapers: &BTreeMap<String, PathBuf>) -> Result<()> {
+        let (conn, screen_num) = connect()?;
+        let screen = &conn.setup().roots[screen_num];
+        let root = screen.root;
+        let depth = screen.root_depth;
+        let (width, height) = (screen.width_in_pixels, screen.height_in_pixels);
+
+        let format = conn.setup().pixmap_formats.iter().find(|format| format.depth == depth);
+        if format.map(|format| format.bits_per_pixel) != Some(32) {
+            bail!("Unsupported root window depth {}", depth);
+        }
+        let msb_first = conn.setup().image_byte_order == ImageOrder::MSB_FIRST;
+
+        let mut monitors = randr_monitors(&conn, root).unwrap_or_default();
+        if monitors.is_empty() {
+            monitors.push(OutputInfo {
+                name: "*".to_string(),
+                x: 0,
+                y: 0,
+                width: width as u32,
+                height: height as u32,
+            });
+        }
+
+        self.images.retain(|path, _| wallpapers.values().any(|p| p == path));
+        let mut canvas = RgbaImage::from_pixel(width as u32, height as u32, Rgba([0, 0, 0, 255]));
+        for monitor in &monitors {
+            let Some(path) = wallpapers.get(&monitor.name).or_else(|| wallpapers.get("*")) else {
+                continue;
+            };
+            if !self.images.contains_key(path) {
+                let image = image::open(path)
+                    .with_context(|| format!("decoding {}", path.display()))?
+                    .into_rgba8();
+                self.images.insert(path.clone(), image);
+            }
+            let part = render::compose(&self.images[path], monitor.width, monitor.height, self.mode);
+            imageops::replace(&mut canvas, &part, monitor.x as i64, monitor.y as i64);
+        }
+
+        let mut data = vec![0; canvas.len()];
+        render::write_xrgb8888(&canvas, &mut data);
+        if msb_first {
+            data.chunks_exact_mut(4).for_each(<[u8]>::reverse);
+        }
+
+        let pixmap = conn.generate_id()?;
+        conn.create_pixmap(depth, pixmap, root, width, height)?;
+        let gc = conn.generate_id()?;
+        conn.create_gc(gc, pixmap, &CreateGCAux::new())?;
+
+        // Stay under the maximum request size, a PutImage header is 24 bytes.
+        let stride = width as usize * 4;
+        let rows = ((conn.maximum_request_bytes() - 24) / stride).max(1);
+        for (index, strip) in data.chunks(rows * stride).enumerate() {
+            let y = (index * rows) as i16;
+            let strip_height = (strip.len() / stride) as u16;
+            conn.put_image(ImageFormat::Z_PIXMAP, pixmap, gc, width, strip_height, 0, y, 0, depth, strip)?;
+        }
+        conn.free_gc(gc)?;
+
+        let xrootpmap = intern(&conn, "_XROOTPMAP_ID")?;
+        let esetroot = intern(&conn, "ESETROOT_PMAP_ID")?;
+        let previous = conn
+            .get_property(false, root, esetroot, AtomEnum::PIXMAP, 0, 1)?
+            .reply()?
+            .value32()
+            .and_then(|mut values| values.next());
+
+        conn.change_property32(PropMode::REPLACE, root, xrootpmap, AtomEnum::PIXMAP, &[pixmap])?;
+        conn.change_property32(PropMode::REPLACE, root, esetroot, AtomEnum::PIXMAP, &[pixmap])?;
+        conn.change_window_attributes(root, &ChangeWindowAttributesAux::new().background_pixmap(pixmap))?;
+        conn.clear_area(false, root, 0, 0, 0, 0)?;
+
+        // The previous setter kept its pixmap alive the same way, killing the
+        // resource frees it.
+        if let Some(previous) = previous.filter(|&previous| previous != 0 && previous != pixmap) {
+            conn.kill_client(previous)?;
+        }
+        conn.set_close_down_mode(CloseDown::RETAIN_PERMANENT)?;
+        conn.sync().context("waiting for the X server")?;
+        Ok(())
+    }
+}
+
+impl Backend for X11 {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_output: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        let mut wallpapers = self.wallpapers.clone();
+        if output.is_none() {
+            wallpapers.clear();
+        }
+        wallpapers.insert(output.unwrap_or("*").to_string(), path.to_path_buf());
+
+        self.draw(&wallpapers)?;
+        println!("Wallpaper {}", path.display());
+        self.wallpapers = wallpapers;
+        Ok(())
+    }
+
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        x11_outputs()
+    }
+}
+
+fn connect() -> Result<(RustConnection, usize)> {
+    x11rb::connect(None).context("connecting to the X server, is DISPLAY set?")
+}
+
+fn intern(conn: &RustConnection, name: &str) -> Result<u32> {
+    Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    fn root_pixmap(conn: &RustConnection, root: u32) -> Option<u32> {
+        let atom = intern(conn, "_XROOTPMAP_ID").unwrap();
+        conn.get_property(false, root, atom, AtomEnum::PIXMAP, 0, 1)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .value32()
+            .and_then(|mut values| values.next())
+    }
+
+    #[test]
+    #[ignore = "needs an X server, run with e.g. `xvfb-run cargo test -- --ignored`"]
+    fn publishes_the_pixmap_and_frees_the_previous_one() {
+        let dir = env::temp_dir().join(format!("wallpaper-manager-test-{}-x11", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let red = dir.join("red.png");
+        let blue = dir.join("blue.png");
+        RgbaImage::from_pixel(64, 32, Rgba([255, 0, 0, 255])).save(&red).unwrap();
+        RgbaImage::from_pixel(32, 64, Rgba([0, 0, 255, 255])).save(&blue).unwrap();
+
+        let (conn, screen_num) = connect().unwrap();
+        let root = conn.setup().roots[screen_num].root;
+        let mut backend = X11::new(ScaleMode::Fill).unwrap();
+
+        backend.set(&red, None).unwrap();
+        let first = root_pixmap(&conn, root).expect("_XROOTPMAP_ID is set");
+        assert!(conn.get_geometry(first).unwrap().reply().is_ok());
+
+        backend.set(&blue, None).unwrap();
+        let second = root_pixmap(&conn, root).expect("_XROOTPMAP_ID is set");
+        assert_ne!(first, second);
+        assert!(conn.get_geometry(second).unwrap().reply().is_ok());
+        assert!(conn.get_geometry(first).unwrap().reply().is_err(), "the previous pixmap is still alive");
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::eyre::bail;
+use color_eyre::Result;
+
+use super::{absolute, run_command, Backend, Capabilities, ScaleMode};
+
+const CHANNEL: &str = "xfce4-desktop";
+
+/// XFCE, through the xfdesktop properties of xfconf.
+///
+/// xfdesktop keeps a `last-image` property per screen, monitor and
+/// workspace, like `/backdrop/screen0/monitoreDP-1/workspace0/last-image`.
+/// Every one of them belonging to the output is set.
+pub struct Xfce {
+    mode: ScaleMode,
+}
+
+impl Xfce {
+    pub fn new(mode: ScaleMode) -> Self {
+        Self { mode }
+    }
+}
+
+impl Backend for Xfce {
+    fn name(&self) -> &'static str {
+        "xfce"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_output: true, ..Default::default() }
+    }
+
+    fn set(&mut self, path: &Path, output: Option<&str>) -> Result<()> {
+        let listed = run_command(Command::new("xfconf-query").arg("-c").arg(CHANNEL).arg("-l"))?;
+        let listed = String::from_utf8_lossy(&listed.stdout);
+        let monitor = output.map(|name| format!("/monitor{}/", name));
+        let properties: Vec<&str> = listed
+            .lines()
+            .filter(|property| property.starts_with("/backdrop/") && property.ends_with("/last-image"))
+            .filter(|property| monitor.as_ref().map_or(true, |monitor| property.contains(monitor.as_str())))
+            .collect();
+
+        if properties.is_empty() {
+            match output {
+                Some(name) => bail!("xfdesktop has no backdrop for output '{}'", name),
+                None => bail!("xfdesktop has no backdrop properties, is xfdesktop running?"),
+            }
+        }
+
+        let style = match self.mode {
+            ScaleMode::Center => "1",
+            ScaleMode::Tile => "2",
+            ScaleMode::Stretch => "3",
+            ScaleMode::Fit => "4",
+            ScaleMode::Fill => "5",
+        };
+        for property in properties {
+            let style_property = property.replace("/last-image", "/image-style");
+            run_command(
+                Command::new("xfconf-query")
+                    .args(["-c", CHANNEL, "-p", &style_property, "-n", "-t", "int", "-s", style]),
+            )?;
+            run_command(Command::new("xfconf-query").args(["-c", CHANNEL, "-p", property, "-s"]).arg(absolute(path)))?;
+        }
+        println!("Wallpaper {}", path.display());
+        Ok(())
+    }
+}
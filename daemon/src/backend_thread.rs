@@ -0,0 +1,106 @@
+//! The thread talking to the backend, so that a slow wallpaper daemon
+//! doesn't hold up the event loop.
+
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+
+use color_eyre::Result;
+use smithay_client_toolkit::reexports::calloop::channel;
+use wallpaper_manager_ipc::{IpcError, Transition};
+
+use crate::backend::{Backend, Capabilities};
+use crate::outputs::OutputInfo;
+use crate::preload::{update_preloaded, PreloadOptions};
+use crate::wallpaper_manager::error_message;
+
+/// A task for the backend thread. Ids increase with each job, which are run
+/// and reported in order.
+pub struct Job {
+    pub id: u64,
+    pub task: Task,
+}
+
+pub enum Task {
+    Show(Change),
+    SetTransition(Transition),
+    /// List the outputs, see [`Backend::outputs`].
+    Outputs,
+    /// Go back to the preferred backend, see [`Backend::recover`].
+    Recover,
+}
+
+/// A wallpaper to show.
+pub struct Change {
+    /// The file given to the backend.
+    pub file: PathBuf,
+    /// The wallpaper it stands for, see `OutputWallpaper::shown`.
+    pub path: PathBuf,
+    pub output: Option<String>,
+    /// The wallpapers in rotation order and the files shown once this one
+    /// is, to update the preloaded images. Empty if the backend doesn't
+    /// preload.
+    pub paths: Vec<PathBuf>,
+    pub shown: Vec<PathBuf>,
+    pub preload: PreloadOptions,
+}
+
+/// The state of the backend after the [`Job`] `id`, with its outcome.
+pub struct Report {
+    pub id: u64,
+    pub name: &'static str,
+    pub capabilities: Capabilities,
+    pub outcome: Outcome,
+}
+
+pub enum Outcome {
+    Shown(Change, Result<(), IpcError>),
+    TransitionSet(Result<(), IpcError>),
+    Outputs(Result<Vec<OutputInfo>>),
+    Recovered,
+}
+
+/// Run the `jobs` one after the other, until either channel is closed.
+pub fn run(backend: Arc<Mutex<Box<dyn Backend>>>, jobs: mpsc::Receiver<Job>, reports: channel::Sender<Report>) {
+    for Job { id, task } in jobs {
+        let mut backend = backend.lock().unwrap();
+        let outcome = match task {
+            Task::Show(change) => {
+                let result = backend.set(&change.file, change.output.as_deref()).map_err(|e| {
+                    eprintln!("Failed to set wallpaper with {}: {:?}", backend.name(), e);
+                    IpcError::BackendFailed {
+                        backend: backend.name().to_string(),
+                        path: change.path.clone(),
+                        message: error_message(&e),
+                    }
+                });
+                Outcome::Shown(change, result)
+            }
+            Task::SetTransition(transition) => Outcome::TransitionSet(
+                backend
+                    .set_transition(&transition)
+                    .map_err(|e| IpcError::Unsupported { backend: backend.name().to_string(), message: e.to_string() }),
+            ),
+            Task::Outputs => Outcome::Outputs(backend.outputs()),
+            Task::Recover => {
+                if let Err(e) = backend.recover() {
+                    eprintln!("The preferred backend is still failing: {:#}", e);
+                }
+                Outcome::Recovered
+            }
+        };
+        let preload = match &outcome {
+            Outcome::Shown(change, _) if backend.capabilities().preload => {
+                Some((change.paths.clone(), change.shown.clone(), change.preload.clone()))
+            }
+            _ => None,
+        };
+
+        let report = Report { id, name: backend.name(), capabilities: backend.capabilities(), outcome };
+        if reports.send(report).is_err() {
+            return;
+        }
+        if let Some((paths, shown, options)) = preload {
+            update_preloaded(backend.as_mut(), &paths, &shown, &options);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Named collections of sources, each with its own interval and order, read
+//! from a JSON file and switched between over IPC.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::sources::Source;
+
+/// Name of the collection given on the command line.
+pub const DEFAULT_COLLECTION: &str = "default";
+
+#[derive(clap::Args, Clone, Debug)]
+#[command(about = None, long_about = None)]
+pub struct CollectionOptions {
+    /// JSON file of named collections to switch between, e.g.
+    /// {"night": {"sources": ["~/Pictures/night"], "interval": 600000, "ordered": true}}.
+    /// The interval and order default to the ones of the command line, whose
+    /// sources are the "default" collection
+    #[clap(long)]
+    pub collections: Option<PathBuf>,
+}
+
+/// A set of sources with the settings to rotate through them.
+#[derive(Clone, Debug)]
+pub struct Collection {
+    pub sources: Vec<Source>,
+    pub interval: Duration,
+    /// Whether directories keep their order rather than being shuffled.
+    pub ordered: bool,
+    /// Where the collection was left, if it was active before.
+    pub saved: Option<Saved>,
+}
+
+/// The queue of a collection when it was switched away from.
+#[derive(Clone, Debug)]
+pub struct Saved {
+    pub paths: Vec<PathBuf>,
+    /// The wallpaper each output was at.
+    pub positions: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CollectionConfig {
+    sources: Vec<String>,
+    /// In milliseconds, like `--interval`.
+    interval: Option<u64>,
+    ordered: Option<bool>,
+}
+
+/// Read the collections in `path`, filling in `interval` and `ordered` where
+/// they aren't set.
+pub fn load(path: &Path, interval: Duration, ordered: bool) -> Result<BTreeMap<String, Collection>> {
+    let contents = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let configs: BTreeMap<String, CollectionConfig> =
+        serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))?;
+
+    let mut collections = BTreeMap::new();
+    for (name, config) in configs {
+        if name == DEFAULT_COLLECTION {
+            bail!("'{}' is the collection given on the command line, pick another name", name);
+        }
+        if config.sources.is_empty() {
+            bail!("The {} collection has no sources", name);
+        }
+        let sources = config
+            .sources
+            .iter()
+            .map(|source| source.parse::<Source>().map(|source| source.absolute()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| eyre!("Invalid source in the {} collection: {}", name, e))?;
+        let collection = Collection {
+            sources,
+            interval: config.interval.map_or(interval, Duration::from_millis),
+            ordered: config.ordered.unwrap_or(ordered),
+            saved: None,
+        };
+        collections.insert(name, collection);
+    }
+    Ok(collections)
+}
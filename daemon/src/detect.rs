@@ -0,0 +1,70 @@
+//! Picking a backend for `--wallpaper-daemon auto` from the environment.
+
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::bail;
+use color_eyre::Result;
+
+use crate::backend::{wait_for_daemon, HyprpaperClient};
+use crate::wallpaper_manager::WallpaperDaemon;
+
+/// The backend picked by [`detect_backend`] and why.
+#[derive(Clone, Debug)]
+pub struct Detection {
+    pub daemon: WallpaperDaemon,
+    pub reason: String,
+}
+
+/// Pick the best backend for the current session.
+///
+/// A wallpaper daemon that is already running wins, then the desktop's own
+/// mechanism, then what the compositor or X server supports.
+pub fn detect_backend() -> Result<Detection> {
+    let detection = |daemon, reason: &str| Ok(Detection { daemon, reason: reason.to_string() });
+
+    if wait_for_daemon(Duration::ZERO) {
+        return detection(WallpaperDaemon::Swww, "swww-daemon is running");
+    }
+    if hyprpaper_running() {
+        return detection(WallpaperDaemon::Hyprpaper, "hyprpaper is running");
+    }
+
+    let desktops = env::var("XDG_CURRENT_DESKTOP").unwrap_or_default().to_ascii_lowercase();
+    for desktop in desktops.split(':') {
+        match desktop {
+            "gnome" | "unity" => return detection(WallpaperDaemon::Gnome, "XDG_CURRENT_DESKTOP is GNOME"),
+            "kde" => return detection(WallpaperDaemon::Kde, "XDG_CURRENT_DESKTOP is KDE"),
+            "xfce" => return detection(WallpaperDaemon::Xfce, "XDG_CURRENT_DESKTOP is XFCE"),
+            _ => {}
+        }
+    }
+
+    if env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        return detection(
+            WallpaperDaemon::LayerShell,
+            "Hyprland is running without swww-daemon or hyprpaper",
+        );
+    }
+    if env::var_os("SWAYSOCK").is_some() && in_path("swaybg") {
+        return detection(WallpaperDaemon::Swaybg, "SWAYSOCK is set and swaybg is installed");
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        return detection(WallpaperDaemon::LayerShell, "WAYLAND_DISPLAY is set");
+    }
+    if env::var_os("DISPLAY").is_some() {
+        return detection(WallpaperDaemon::X11, "DISPLAY is set without WAYLAND_DISPLAY");
+    }
+    bail!("No graphical session found, neither WAYLAND_DISPLAY nor DISPLAY is set")
+}
+
+fn hyprpaper_running() -> bool {
+    HyprpaperClient::from_env().is_ok_and(|client| client.request("listloaded").is_ok())
+}
+
+pub(crate) fn in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
@@ -3,6 +3,7 @@
 
 use std::fs;
 use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 use std::time::Instant;
@@ -11,6 +12,7 @@ use color_eyre::eyre::{ensure, Context};
 use color_eyre::{Result, Section};
 use wallpaper_manager_ipc::{IpcError, IpcMessage, IpcResponse};
 
+use crate::backend_thread::Task;
 use crate::socket::SocketSource;
 use crate::WallpaperManager;
 
@@ -39,6 +41,23 @@ pub fn listen_on_ipc_socket(socket_path: &Path) -> Result<SocketSource> {
     Ok(socket)
 }
 
+/// A reply held back until the backend thread ran the jobs `first` to `last`
+/// queued by its request.
+pub struct PendingReply {
+    stream: UnixStream,
+    first: u64,
+    last: u64,
+    response: Result<IpcResponse, IpcError>,
+}
+
+/// A request naming an output the daemon doesn't know yet, answered once the
+/// job `job` listed the outputs again.
+pub struct Deferred {
+    stream: UnixStream,
+    job: u64,
+    message: IpcMessage,
+}
+
 pub fn handle_message(
     ustream: UnixStream,
     wallpaper_manager: &mut WallpaperManager,
@@ -59,64 +78,74 @@ pub fn handle_message(
 
     let message: IpcMessage = serde_json::from_slice(&buffer[..n])
         .with_context(|| format!("error while deserializing message {:?}", &buffer[..n]))?;
+    answer(ustream, message, wallpaper_manager, true)
+}
+
+/// Handle `message`, listing the outputs first if it names an unknown one and
+/// `refresh` is set. The reply waits for the changes queued for the backend.
+fn answer(
+    ustream: UnixStream,
+    message: IpcMessage,
+    wallpaper_manager: &mut WallpaperManager,
+    refresh: bool,
+) -> Result<()> {
+    let output = match &message {
+        IpcMessage::NextWallpaper { output }
+        | IpcMessage::PreviousWallpaper { output }
+        | IpcMessage::GoToWallpaper { output, .. } => output.as_ref(),
+        _ => None,
+    };
+    if refresh && output.is_some_and(|output| !wallpaper_manager.outputs.contains_key(output)) {
+        if let Some(job) = wallpaper_manager.send(Task::Outputs) {
+            wallpaper_manager.deferred.push(Deferred { stream: ustream, job, message });
+            return Ok(());
+        }
+    }
+
+    let first = wallpaper_manager.next_job;
     let mut should_stop = false;
 
     let resp: Result<IpcResponse, IpcError> = match message {
-        IpcMessage::StopDaemon { } => Ok({
+        IpcMessage::StopDaemon => Ok({
             should_stop = true;
             IpcResponse::Ok
         }),
-        IpcMessage::PausePlay { } => Ok({
+        IpcMessage::PausePlay => Ok({
             if !wallpaper_manager.is_paused {
                 wallpaper_manager.is_paused = true;
                 wallpaper_manager.last_pause = Some(Instant::now());
             }
             IpcResponse::Ok
         }),
-        IpcMessage::ResumePlay { } => Ok({
+        IpcMessage::ResumePlay => Ok({
             if wallpaper_manager.is_paused {
                 wallpaper_manager.is_paused = false;
                 wallpaper_manager.last_resume = Some(Instant::now());
             }
             IpcResponse::Ok
         }),
-        IpcMessage::NextWallpaper { } => Ok({
-            wallpaper_manager.paths.rotate_left(1);
-            wallpaper_manager.set_wallpaper(wallpaper_manager.paths[0].clone()).unwrap();
-            wallpaper_manager.skip_after_manual = true;
-            IpcResponse::Ok
-        }),
-        IpcMessage::PreviousWallpaper { } => Ok({
-            wallpaper_manager.paths.rotate_right(1);
-            wallpaper_manager.set_wallpaper(wallpaper_manager.paths[0].clone()).unwrap();
-            wallpaper_manager.skip_after_manual = true;
-            IpcResponse::Ok
-        }),
+        IpcMessage::NextWallpaper { output } => {
+            wallpaper_manager.step(output.as_deref(), true).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::PreviousWallpaper { output } => {
+            wallpaper_manager.step(output.as_deref(), false).map(|_| IpcResponse::Ok)
+        },
         IpcMessage::MoveWallpaperToIndex { path, index } => {
             if let Some(prev_index) = wallpaper_manager.paths.iter().position(|x| x == &path) {
                 wallpaper_manager.paths.remove(prev_index);
                 wallpaper_manager.paths.insert(index, path);
 
                 if index == 0 || prev_index == 0 {
-                    wallpaper_manager.set_wallpaper(wallpaper_manager.paths[0].clone()).unwrap();
-                    wallpaper_manager.skip_after_manual = true;
+                    wallpaper_manager.show_current().map(|_| IpcResponse::Ok)
+                } else {
+                    Ok(IpcResponse::Ok)
                 }
-        
-                Ok(IpcResponse::Ok)
             } else {
                 Err(IpcError::PathNotAdded { path })
             }
         },
-        IpcMessage::GoToWallpaper { path } => {
-            if let Some(index) = wallpaper_manager.paths.iter().position(|x| x == &path) {
-                wallpaper_manager.paths.rotate_left(index);
-                wallpaper_manager.set_wallpaper(wallpaper_manager.paths[0].clone()).unwrap();
-                wallpaper_manager.skip_after_manual = true;
-        
-                Ok(IpcResponse::Ok)
-            } else {
-                Err(IpcError::PathNotAdded { path })
-            }
+        IpcMessage::GoToWallpaper { path, output } => {
+            wallpaper_manager.go_to(path, output.as_deref()).map(|_| IpcResponse::Ok)
         },
         IpcMessage::AllWallpapers => Ok(IpcResponse::AllWallpapers {
             entries: wallpaper_manager.paths.clone()
@@ -140,21 +169,118 @@ pub fn handle_message(
 
                 (last_pause - last_update).as_millis() + last_resume.elapsed().as_millis()
             }
-        }),  
+        }),
+        IpcMessage::CurrentWallpapers => Ok(IpcResponse::CurrentWallpapers {
+            entries: wallpaper_manager
+                .outputs
+                .iter()
+                .filter_map(|(name, output)| Some((name.clone(), output.current.clone()?)))
+                .collect(),
+        }),
+        IpcMessage::LastError => Ok(IpcResponse::LastError {
+            error: wallpaper_manager.last_error.clone(),
+        }),
+        IpcMessage::SetTransition { transition } => {
+            wallpaper_manager.set_transition(transition).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::Status => Ok(IpcResponse::Status {
+            backend: wallpaper_manager.backend_name.to_string(),
+            chain: wallpaper_manager.backend_chain.clone(),
+            reason: wallpaper_manager.backend_reason.clone(),
+            is_paused: wallpaper_manager.is_paused,
+            wallpapers: wallpaper_manager.paths.len(),
+            collection: wallpaper_manager.collection.clone(),
+            collections: wallpaper_manager.collections.keys().cloned().collect(),
+        }),
+        IpcMessage::SetFilters { include, exclude } => {
+            wallpaper_manager.set_filters(&include, &exclude).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::AddWallpaper { path, position } => {
+            wallpaper_manager.add_wallpaper(path, position).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::RemoveWallpaper { path } => {
+            wallpaper_manager.remove_wallpaper(path).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::AddDirectory { dir, recursive } => {
+            wallpaper_manager.add_directory(dir, recursive).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::RemoveDirectory { dir } => {
+            wallpaper_manager.remove_directory(dir).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::SavePlaylist { path } => {
+            wallpaper_manager.save_playlist(path).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::SwitchCollection { name } => {
+            wallpaper_manager.switch_collection(&name).map(|_| IpcResponse::Ok)
+        },
+        IpcMessage::InvalidWallpapers => Ok(IpcResponse::InvalidWallpapers {
+            entries: wallpaper_manager.quarantine.clone(),
+        }),
+        IpcMessage::SetResolutionRules { rules } => rules.validate().map(|_| {
+            wallpaper_manager.resolution_rules = rules;
+            IpcResponse::Ok
+        }),
+        IpcMessage::ResolutionRules => Ok(IpcResponse::ResolutionRules {
+            rules: wallpaper_manager.resolution_rules.clone(),
+        }),
     };
 
-    let mut stream = BufWriter::new(ustream);
-    stream
-        .write_all(&serde_json::to_vec(&resp).unwrap())
-        .context("unable to write response to the IPC client")
-        .suggestion("Probably the client died, try running it again")?;
-
-    stream.flush()?;
+    if resp.is_ok() && wallpaper_manager.next_job > first && !should_stop {
+        let last = wallpaper_manager.next_job - 1;
+        wallpaper_manager.replies.push(PendingReply { stream: ustream, first, last, response: resp });
+        return Ok(());
+    }
+    reply(ustream, &resp)?;
 
     if should_stop {
+        for pending in mem::take(&mut wallpaper_manager.replies) {
+            let _ = reply(pending.stream, &pending.response);
+        }
         std::fs::remove_file(wallpaper_manager.socket_path.clone()).expect("Unable to remove socket file");
         std::process::exit(0);
     }
     
     Ok(())
 }
+
+/// Answer the requests waiting for the job `id` of the backend thread, which
+/// ended with `outcome`. A failed job fails the request that queued it.
+pub fn reply_pending(id: u64, outcome: Result<(), IpcError>, wallpaper_manager: &mut WallpaperManager) {
+    let (done, waiting): (Vec<_>, Vec<_>) = mem::take(&mut wallpaper_manager.replies)
+        .into_iter()
+        .map(|mut pending| {
+            if let (Ok(_), Err(e)) = (&pending.response, &outcome) {
+                if (pending.first..=pending.last).contains(&id) {
+                    pending.response = Err(e.clone());
+                }
+            }
+            pending
+        })
+        .partition(|pending| pending.last <= id);
+    wallpaper_manager.replies = waiting;
+    for pending in done {
+        if let Err(e) = reply(pending.stream, &pending.response) {
+            println!("{:?}", e);
+        }
+    }
+
+    let (ready, deferred): (Vec<_>, Vec<_>) =
+        mem::take(&mut wallpaper_manager.deferred).into_iter().partition(|deferred| deferred.job <= id);
+    wallpaper_manager.deferred = deferred;
+    for deferred in ready {
+        if let Err(e) = answer(deferred.stream, deferred.message, wallpaper_manager, false) {
+            println!("{:?}", e);
+        }
+    }
+}
+
+fn reply(ustream: UnixStream, resp: &Result<IpcResponse, IpcError>) -> Result<()> {
+    let mut stream = BufWriter::new(ustream);
+    stream
+        .write_all(&serde_json::to_vec(resp).unwrap())
+        .context("unable to write response to the IPC client")
+        .suggestion("Probably the client died, try running it again")?;
+
+    stream.flush()?;
+    Ok(())
+}
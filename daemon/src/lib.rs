@@ -1,36 +1,127 @@
+pub mod backend;
+mod backend_thread;
+pub mod collections;
+pub mod detect;
 mod ipc_server;
+pub mod media;
+pub mod outputs;
+pub mod playlist;
+pub mod preload;
+mod render;
+pub mod resolution;
+pub mod scan;
 mod shuffle;
+pub mod sources;
+mod span;
 mod socket;
+mod watcher;
 pub mod wallpaper_manager;
 
-use std::path::PathBuf;
-use std::time::Duration;
+use std::env;
+use std::time::{Duration, Instant};
 
-use ipc_server::{handle_message, listen_on_ipc_socket};
+use ipc_server::{handle_message, listen_on_ipc_socket, reply_pending};
 use wallpaper_manager_ipc::socket_path;
 use color_eyre::{
     eyre::WrapErr,
     Result,
 };
 use smithay_client_toolkit::reexports::{
-    calloop::{self, timer::{Timer, TimeoutAction}},
+    calloop::{self, channel, ping::make_ping, timer::{Timer, TimeoutAction}},
 };
 
-use crate::shuffle::shuffle;
-use crate::wallpaper_manager::{WallpaperManager, WallpaperDaemon};
+use crate::backend::{Backend, BackendOptions, Fallback, Handoff, Mpvpaper};
+use crate::collections::{load, CollectionOptions};
+use crate::detect::{detect_backend, in_path};
+use crate::preload::PreloadOptions;
+use crate::resolution::ResolutionOptions;
+use crate::scan::ScanOptions;
+use crate::sources::{collect, Source};
+use crate::watcher::{WatchEvent, WatchSource};
+use crate::wallpaper_manager::{Rotation, WallpaperManager, WallpaperDaemon};
 
-pub fn run(dir: PathBuf, interval: u64, wallpaper_daemon: WallpaperDaemon) -> Result<()> {
+// Each argument is a group of `start-daemon` options.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    sources: Vec<Source>,
+    interval: u64,
+    wallpaper_daemons: Vec<WallpaperDaemon>,
+    backend_options: BackendOptions,
+    mut rotation: Rotation,
+    preload_options: PreloadOptions,
+    scan_options: ScanOptions,
+    collection_options: CollectionOptions,
+    resolution_options: ResolutionOptions,
+) -> Result<()> {
     let mut event_loop = calloop::EventLoop::<WallpaperManager>::try_new()?;
-    let mut wallpaper_manager = WallpaperManager::new(dir.clone(), Duration::from_millis(interval), wallpaper_daemon, socket_path()?)?;
+    let sources: Vec<Source> = sources.iter().map(Source::absolute).collect();
+    let mut backend_reason = None;
+    let mut daemons: Vec<WallpaperDaemon> = Vec::new();
+    for daemon in &wallpaper_daemons {
+        let daemon = match daemon {
+            WallpaperDaemon::Auto => match detect_backend() {
+                Ok(detection) => {
+                    println!("Using the {} backend: {}", detection.daemon.name(), detection.reason);
+                    backend_reason = Some(detection.reason);
+                    detection.daemon
+                }
+                Err(e) if wallpaper_daemons.len() > 1 => {
+                    eprintln!("Failed to detect a backend: {:?}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            },
+            daemon => daemon.clone(),
+        };
+        if !daemons.contains(&daemon) {
+            daemons.push(daemon);
+        }
+    }
+    let backend_chain: Vec<String> = daemons.iter().map(WallpaperDaemon::name).collect();
+    let under_mpvpaper = daemons.iter().all(WallpaperDaemon::under_mpvpaper);
+    let mut backend: Box<dyn Backend> = match daemons.as_slice() {
+        [daemon] => daemon.backend(&backend_options)?,
+        _ => Box::new(Fallback::new(daemons, backend_options.clone())?),
+    };
+    if !backend.capabilities().video && under_mpvpaper && env::var_os("WAYLAND_DISPLAY").is_some() && in_path("mpvpaper") {
+        println!("{} can't play videos, handing them off to mpvpaper", backend.name());
+        let player = Mpvpaper::new(&backend_options.mpv_options, backend_options.mode);
+        backend = Box::new(Handoff::new(backend, player));
+    }
+    if rotation != Rotation::Synchronized && !backend.capabilities().per_output {
+        println!("{} can't show different wallpapers per output, using synchronized rotation", backend.name());
+        rotation = Rotation::Synchronized;
+    }
+    let mut wallpaper_manager = WallpaperManager::new(sources.clone(), scan_options.clone(), Duration::from_millis(interval), backend, socket_path()?, rotation, preload_options)?;
+    wallpaper_manager.media_fallback = backend_options.media_fallback;
+    wallpaper_manager.backend_reason = backend_reason;
+    wallpaper_manager.backend_chain = backend_chain;
+    wallpaper_manager.resolution_rules = resolution_options.into();
+    if let Some(path) = &collection_options.collections {
+        wallpaper_manager.collections = load(path, wallpaper_manager.interval, scan_options.ordered)?;
+    }
+    if let Err(e) = wallpaper_manager.refresh_outputs() {
+        println!("Unable to list outputs, wallpapers will be set on all of them: {:?}", e);
+    }
     
-    wallpaper_manager.paths = std::fs::read_dir(dir.clone()).unwrap()
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, std::io::Error>>().unwrap();
-    shuffle(&mut wallpaper_manager.paths);
+    let paths = collect(&sources, &scan_options, &mut wallpaper_manager.origins)?;
+    wallpaper_manager.paths = wallpaper_manager.validated(paths);
 
     let paths_length = wallpaper_manager.paths.len();
     println!("Total wallpapers: {}", paths_length);
 
+    let reports = wallpaper_manager.spawn_backend_thread();
+    event_loop
+        .handle()
+        .insert_source(reports, |event, _, wallpaper_manager| {
+            if let channel::Event::Msg(report) = event {
+                let id = report.id;
+                let outcome = wallpaper_manager.report(report);
+                reply_pending(id, outcome, wallpaper_manager);
+            }
+        })
+        .map_err(|e| e.error)?;
+
     let socket = listen_on_ipc_socket(&wallpaper_manager.socket_path).context("spawning the ipc socket")?;
 
     event_loop
@@ -41,40 +132,50 @@ pub fn run(dir: PathBuf, interval: u64, wallpaper_daemon: WallpaperDaemon) -> Re
             }
         })?;
 
-    let source = Timer::from_duration(Duration::from_secs(0));
+    let watcher = WatchSource::new().context("watching the wallpaper sources")?;
+    let handle = watcher.watcher();
+    for source in &sources {
+        if let Some((dir, depth)) = source.watch_root(&scan_options) {
+            handle.watch(&dir, depth);
+        }
+    }
+    wallpaper_manager.watcher = Some(handle);
 
     event_loop
         .handle()
-        .insert_source(source, |_event, _metadata, wallpaper_manager| {
-            if wallpaper_manager.waiting_after_pause {
-                wallpaper_manager.last_pause = None;
-                wallpaper_manager.last_resume = None;
-                wallpaper_manager.waiting_after_pause = false;
-            }
-            if wallpaper_manager.is_paused {
-                return TimeoutAction::ToDuration(Duration::from_millis(10));
-            }
-            if wallpaper_manager.skip_after_manual {
-                wallpaper_manager.skip_after_manual = false;
-                return TimeoutAction::ToDuration((wallpaper_manager.interval) - (wallpaper_manager.last_update.unwrap().elapsed()));
-            }
-            if wallpaper_manager.last_pause != None {
-                let last_pause_clone = wallpaper_manager.last_pause.clone().unwrap();
-                let last_resume_clone = wallpaper_manager.last_resume.clone().unwrap();
-                wallpaper_manager.waiting_after_pause = true;
-
-                return TimeoutAction::ToDuration(
-                    wallpaper_manager.interval
-                    - std::time::Instant::duration_since(&std::time::Instant::now(), last_resume_clone)
-                    - std::time::Instant::duration_since(&last_pause_clone, wallpaper_manager.last_update.unwrap())
-                );
-            }
+        .insert_source(watcher, |event, _, wallpaper_manager| match event {
+            WatchEvent::Added(path) => wallpaper_manager.add_found(&path),
+            WatchEvent::Removed(path) => wallpaper_manager.remove_path(&path),
+        })
+        .map_err(|e| e.error)?;
 
-            wallpaper_manager.paths.rotate_left(1);
-            wallpaper_manager.set_wallpaper(wallpaper_manager.paths[0].clone()).unwrap();
+    let handle = event_loop.handle();
+    let mut rotation_timer = handle.insert_source(Timer::immediate(), rotate).map_err(|e| e.error)?;
+    let (rearm, rearm_source) = make_ping()?;
+    wallpaper_manager.rearm = Some(rearm);
+    event_loop
+        .handle()
+        .insert_source(rearm_source, move |_, _, _| {
+            // The new timer fires right away, and waits for the rest of the
+            // new interval if a wallpaper was just shown.
+            handle.remove(rotation_timer);
+            match handle.insert_source(Timer::immediate(), rotate) {
+                Ok(token) => rotation_timer = token,
+                Err(e) => eprintln!("Failed to restart the rotation: {}", e.error),
+            }
+        })
+        .map_err(|e| e.error)?;
 
-            TimeoutAction::ToDuration(wallpaper_manager.interval)
-        }).unwrap();
+    if wallpaper_manager.backend_chain.len() > 1 {
+        let probe_interval = Duration::from_secs(backend_options.fallback_probe_interval.max(1));
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(probe_interval), move |_, _, wallpaper_manager| {
+                wallpaper_manager.recover();
+                TimeoutAction::ToDuration(probe_interval)
+            })
+            .map_err(|e| e.error)?;
+    }
 
     loop {
         event_loop
@@ -82,3 +183,43 @@ pub fn run(dir: PathBuf, interval: u64, wallpaper_daemon: WallpaperDaemon) -> Re
             .context("dispatching the event loop")?;
     }
 }
+
+/// Move on to the next wallpaper, returning when to do it again.
+fn rotate(_: Instant, _: &mut (), wallpaper_manager: &mut WallpaperManager) -> TimeoutAction {
+    if wallpaper_manager.waiting_after_pause {
+        wallpaper_manager.last_pause = None;
+        wallpaper_manager.last_resume = None;
+        wallpaper_manager.waiting_after_pause = false;
+    }
+    if wallpaper_manager.is_paused {
+        return TimeoutAction::ToDuration(Duration::from_millis(10));
+    }
+    let since_update = wallpaper_manager.last_update.map_or(Duration::ZERO, |last_update| last_update.elapsed());
+    if wallpaper_manager.skip_after_manual {
+        wallpaper_manager.skip_after_manual = false;
+        return TimeoutAction::ToDuration(wallpaper_manager.interval.saturating_sub(since_update));
+    }
+    if let Some(last_pause) = wallpaper_manager.last_pause {
+        let since_resume = wallpaper_manager.last_resume.map_or(Duration::ZERO, |last_resume| last_resume.elapsed());
+        wallpaper_manager.waiting_after_pause = true;
+
+        return TimeoutAction::ToDuration(
+            wallpaper_manager
+                .interval
+                .saturating_sub(since_resume)
+                .saturating_sub(since_update.saturating_sub(last_pause.elapsed())),
+        );
+    }
+
+    // Failures are logged and kept in `last_error` by the backend thread.
+    if wallpaper_manager.last_update.is_none() {
+        // Start with the first wallpaper, a playlist's first entry.
+        let _ = wallpaper_manager.show_current();
+    } else {
+        let _ = wallpaper_manager.step(None, true);
+    }
+    // Only wallpapers shown outside the rotation shorten the next interval.
+    wallpaper_manager.skip_after_manual = false;
+
+    TimeoutAction::ToDuration(wallpaper_manager.interval)
+}
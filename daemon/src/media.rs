@@ -0,0 +1,198 @@
+//! Recognizing wallpapers, telling still images, animated images and videos
+//! apart, and turning the latter into still frames for backends that can only
+//! show images.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, ImageFormat, ImageReader};
+use xdg::BaseDirectories;
+
+use crate::backend::{run_command, Capabilities};
+
+/// Extensions played as videos.
+const VIDEO_EXTENSIONS: [&str; 8] = ["mp4", "m4v", "mkv", "webm", "mov", "avi", "ogv", "wmv"];
+
+/// How long a still frame stays in the cache after it was last used.
+const STILL_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Image formats the daemon can decode itself, see the `image` features in
+/// Cargo.toml.
+const IMAGE_FORMATS: [ImageFormat; 5] =
+    [ImageFormat::Bmp, ImageFormat::Gif, ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Still,
+    /// A GIF or WebP with more than one frame.
+    Animated,
+    Video,
+}
+
+impl MediaKind {
+    /// Whether a backend with `capabilities` can show this kind as it is.
+    pub fn supported_by(&self, capabilities: Capabilities) -> bool {
+        match self {
+            MediaKind::Still => true,
+            MediaKind::Animated => capabilities.animated,
+            MediaKind::Video => capabilities.video,
+        }
+    }
+}
+
+/// What to do with animated images and videos the backend can't show.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MediaFallback {
+    /// Show their first frame
+    #[default]
+    Still,
+    /// Leave them out of the rotation
+    Skip,
+}
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| VIDEO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/// Whether the extension of `path` is one of a supported image or video,
+/// whatever the file holds.
+pub fn has_media_extension(path: &Path) -> bool {
+    is_video(path) || ImageFormat::from_path(path).is_ok_and(|format| IMAGE_FORMATS.contains(&format))
+}
+
+/// Check that `path` is a supported image or video the backends can decode,
+/// judging by its header, or say why not. Its first bytes must match its
+/// extension, as the backends pick a decoder by either, and the dimensions of
+/// an image must be readable. Returns them, videos have none.
+pub fn validate(path: &Path) -> Result<Option<(u32, u32)>, String> {
+    if !has_media_extension(path) {
+        return Err("not a supported image or video".to_string());
+    }
+    let mut header = [0; 16];
+    let read = File::open(path).and_then(|mut file| file.read(&mut header)).map_err(|e| e.to_string())?;
+    let header = &header[..read];
+
+    if is_video(path) {
+        return if is_video_header(header) { Ok(None) } else { Err("not a video file".to_string()) };
+    }
+    let by_extension = ImageFormat::from_path(path).map_err(|e| e.to_string())?;
+    match image::guess_format(header) {
+        Ok(by_content) if by_content == by_extension => {}
+        Ok(by_content) => {
+            return Err(format!("holds a {:?} image, not {:?}", by_content, by_extension));
+        }
+        Err(_) => return Err(format!("not a {:?} image", by_extension)),
+    }
+
+    let (width, height) = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| format!("unreadable header: {}", e))?;
+    if width == 0 || height == 0 {
+        return Err(format!("empty image of {}x{}", width, height));
+    }
+    Ok(Some((width, height)))
+}
+
+/// Signatures of the containers in `VIDEO_EXTENSIONS`.
+fn is_video_header(header: &[u8]) -> bool {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    at(4, b"ftyp") // MP4, M4V and MOV
+        || at(0, &[0x1a, 0x45, 0xdf, 0xa3]) // Matroska and WebM
+        || (at(0, b"RIFF") && at(8, b"AVI "))
+        || at(0, b"OggS")
+        || at(0, &[0x30, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11]) // ASF (WMV)
+}
+
+/// Classify `path` by its extension, decoding up to two frames of GIF and
+/// WebP files to find out whether they are animated.
+pub fn media_kind(path: &Path) -> MediaKind {
+    if is_video(path) {
+        return MediaKind::Video;
+    }
+    let animated = match ImageFormat::from_path(path) {
+        Ok(ImageFormat::Gif) => File::open(path)
+            .ok()
+            .and_then(|file| GifDecoder::new(BufReader::new(file)).ok())
+            .is_some_and(|decoder| decoder.into_frames().take(2).count() > 1),
+        Ok(ImageFormat::WebP) => File::open(path)
+            .ok()
+            .and_then(|file| WebPDecoder::new(BufReader::new(file)).ok())
+            .is_some_and(|decoder| decoder.has_animation()),
+        _ => false,
+    };
+    if animated {
+        MediaKind::Animated
+    } else {
+        MediaKind::Still
+    }
+}
+
+/// The first frame of `path` as a PNG in the XDG cache directory, extracted
+/// with ffmpeg for videos. Frames from earlier calls are reused, and those
+/// unused for `STILL_MAX_AGE` are removed when a new one is made.
+pub fn still_frame(path: &Path, kind: MediaKind) -> Result<PathBuf> {
+    let xdg_dirs = BaseDirectories::with_prefix("wallpaper-manager")?;
+    let cache_dir = xdg_dirs.create_cache_directory("stills")?;
+
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    fs::metadata(path)?.modified()?.hash(&mut hasher);
+    let still = cache_dir.join(format!("{:016x}.png", hasher.finish()));
+    if let Ok(file) = File::options().write(true).open(&still) {
+        // The modification time tells when the frame was last used.
+        if let Err(e) = file.set_modified(SystemTime::now()) {
+            eprintln!("Failed to touch {}: {}", still.display(), e);
+        }
+        return Ok(still);
+    }
+    prune_stills(&cache_dir);
+
+    if kind == MediaKind::Video {
+        run_command(
+            Command::new("ffmpeg")
+                .args(["-loglevel", "error", "-y", "-i"])
+                .arg(path)
+                .args(["-frames:v", "1"])
+                .arg(&still),
+        )?;
+    } else {
+        image::open(path)
+            .with_context(|| format!("decoding {}", path.display()))?
+            .save_with_format(&still, ImageFormat::Png)
+            .with_context(|| format!("writing {}", still.display()))?;
+    }
+    Ok(still)
+}
+
+/// Remove the still frames in `cache_dir` unused for `STILL_MAX_AGE`.
+fn prune_stills(cache_dir: &Path) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > STILL_MAX_AGE);
+        if stale {
+            if let Err(e) = fs::remove_file(entry.path()) {
+                eprintln!("Failed to remove {}: {}", entry.path().display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Output (monitor) discovery.
+
+use std::env;
+
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use smithay_client_toolkit::{
+    delegate_output, delegate_registry,
+    output::{OutputHandler, OutputState},
+    reexports::client::{
+        globals::registry_queue_init, protocol::wl_output, Connection, QueueHandle,
+    },
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+};
+use x11rb::connection::Connection as _;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::xproto::{ConnectionExt as _, Window};
+use x11rb::rust_connection::RustConnection;
+
+/// An output and its place in the global compositor space, in logical pixels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputInfo {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// List the outputs of the current session, Wayland or X11.
+pub fn session_outputs() -> Result<Vec<OutputInfo>> {
+    if env::var_os("WAYLAND_DISPLAY").is_some() || env::var_os("DISPLAY").is_none() {
+        wayland_outputs()
+    } else {
+        x11_outputs()
+    }
+}
+
+/// List the outputs of the current Wayland session.
+pub fn wayland_outputs() -> Result<Vec<OutputInfo>> {
+    let conn = Connection::connect_to_env().context("connecting to the Wayland compositor")?;
+    let (globals, mut event_queue) = registry_queue_init(&conn)?;
+    let qh = event_queue.handle();
+
+    let mut state = OutputList {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+    // The first roundtrip binds the outputs, the second receives their info.
+    event_queue.roundtrip(&mut state)?;
+    event_queue.roundtrip(&mut state)?;
+
+    let mut outputs: Vec<OutputInfo> = state
+        .output_state
+        .outputs()
+        .filter_map(|output| state.output_state.info(&output))
+        .filter_map(|info| {
+            let name = info.name.clone()?;
+            let (x, y) = info.logical_position.unwrap_or(info.location);
+            let (width, height) = info.logical_size.or_else(|| {
+                let mode = info.modes.iter().find(|mode| mode.current)?;
+                let scale = info.scale_factor.max(1);
+                Some((mode.dimensions.0 / scale, mode.dimensions.1 / scale))
+            })?;
+            Some(OutputInfo { name, x, y, width: width as u32, height: height as u32 })
+        })
+        .collect();
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(outputs)
+}
+
+/// List the monitors of the default X11 screen.
+pub fn x11_outputs() -> Result<Vec<OutputInfo>> {
+    let (conn, screen_num) = x11rb::connect(None).context("connecting to the X server")?;
+    randr_monitors(&conn, conn.setup().roots[screen_num].root)
+}
+
+/// The active RandR monitors of the screen of `root`, sorted by name.
+pub(crate) fn randr_monitors(conn: &RustConnection, root: Window) -> Result<Vec<OutputInfo>> {
+    let reply = conn
+        .randr_get_monitors(root, true)
+        .map_err(|e| eyre!("RandR is not available: {}", e))?
+        .reply()?;
+
+    let mut monitors = Vec::new();
+    for monitor in reply.monitors {
+        let name = conn.get_atom_name(monitor.name)?.reply()?.name;
+        monitors.push(OutputInfo {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            x: monitor.x as i32,
+            y: monitor.y as i32,
+            width: monitor.width as u32,
+            height: monitor.height as u32,
+        });
+    }
+    monitors.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(monitors)
+}
+
+struct OutputList {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+impl OutputHandler for OutputList {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {
+    }
+}
+
+impl ProvidesRegistryState for OutputList {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
+
+delegate_output!(OutputList);
+delegate_registry!(OutputList);
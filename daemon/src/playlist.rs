@@ -0,0 +1,101 @@
+//! Playlists: M3U files, or plain lists with one path per line, used as
+//! sources that keep their order.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+
+use crate::media::has_media_extension;
+
+/// Extensions of the files read as playlists rather than images.
+const PLAYLIST_EXTENSIONS: [&str; 3] = ["m3u", "m3u8", "txt"];
+
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| PLAYLIST_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/// The images and videos listed in `path`, in order and with repeated
+/// entries kept, though the queue only takes the first of them, see
+/// [`crate::sources::interleave`]. Relative entries are relative to the
+/// playlist, lines starting with `#` are comments or M3U directives. Entries
+/// are read as bytes, so paths that aren't UTF-8 are kept as they are.
+pub fn read(path: &Path) -> Result<Vec<PathBuf>> {
+    let contents = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut paths = Vec::new();
+    for line in contents.split(|&byte| byte == b'\n').map(trim) {
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        let entry = dir.join(OsStr::from_bytes(line));
+        if has_media_extension(&entry) {
+            paths.push(entry);
+        } else {
+            println!(
+                "Skipping {} in {}, it is not a supported image or video",
+                String::from_utf8_lossy(line),
+                path.display()
+            );
+        }
+    }
+    Ok(paths)
+}
+
+/// `line` without the whitespace around it, including the `\r` of CRLF files.
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(line.len());
+    let end = line.iter().rposition(|byte| !byte.is_ascii_whitespace()).map_or(start, |end| end + 1);
+    &line[start..end]
+}
+
+/// Write `paths` to `path` as an M3U playlist, which reads as a plain list
+/// too. The paths are written as raw bytes.
+pub fn write(path: &Path, paths: &[PathBuf]) -> Result<()> {
+    let mut file = fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    writeln!(file, "#EXTM3U")?;
+    for entry in paths {
+        file.write_all(entry.as_os_str().as_bytes())?;
+        file.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn written_playlists_read_back() {
+        let dir = env::temp_dir().join(format!("wallpaper-manager-test-{}-playlist", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let playlist = dir.join("saved.m3u");
+        let paths = [
+            dir.join("a b.png"),
+            PathBuf::from(OsStr::from_bytes(b"/wallpapers/\xff\xfe.jpg")),
+            dir.join("a b.png"),
+        ];
+        write(&playlist, &paths).unwrap();
+        assert_eq!(read(&playlist).unwrap(), paths);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn comments_blank_lines_and_other_files_are_skipped() {
+        let dir = env::temp_dir().join(format!("wallpaper-manager-test-{}-playlist-read", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let playlist = dir.join("list.txt");
+        fs::write(&playlist, "#EXTM3U\r\n  a.png \r\n\r\n# b.png\nnotes.txt\n/c.webm").unwrap();
+        assert_eq!(read(&playlist).unwrap(), [dir.join("a.png"), PathBuf::from("/c.webm")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
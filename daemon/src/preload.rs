@@ -0,0 +1,208 @@
+//! Choosing which wallpapers a backend keeps preloaded.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backend::Backend;
+use crate::media::is_video;
+
+/// Preloading settings given to `start-daemon`.
+#[derive(clap::Args, Clone, Debug)]
+#[command(about = None, long_about = None)]
+pub struct PreloadOptions {
+    /// Number of upcoming wallpapers to keep preloaded
+    #[clap(long, default_value_t = 5)]
+    pub preload_ahead: usize,
+    /// Number of previous wallpapers to keep preloaded
+    #[clap(long, default_value_t = 3)]
+    pub preload_behind: usize,
+    /// Upper bound for the decoded size of the preloaded wallpapers, e.g. 512M
+    #[clap(long, value_parser = parse_size)]
+    pub preload_budget: Option<u64>,
+}
+
+/// Parse a byte count with an optional K, M or G suffix.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size '{}': {}", size, e))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", size))
+}
+
+/// Indices of `paths` to preload, most important first: the current one,
+/// then alternating between the next and the previous ones. Every index
+/// appears at most once, however short the list is.
+pub fn preload_order(len: usize, ahead: usize, behind: usize) -> Vec<usize> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let ahead = ahead.min(len - 1);
+    let behind = behind.min(len - 1 - ahead);
+
+    let mut order = vec![0];
+    for i in 1..=ahead.max(behind) {
+        if i <= ahead {
+            order.push(i);
+        }
+        if i <= behind {
+            order.push(len - i);
+        }
+    }
+    order
+}
+
+/// Memory an image takes once decoded, falling back to its file size.
+fn estimated_size(path: &Path) -> u64 {
+    match image::image_dimensions(path) {
+        Ok((width, height)) => width as u64 * height as u64 * 4,
+        Err(_) => fs::metadata(path).map_or(0, |metadata| metadata.len()),
+    }
+}
+
+/// Keep the wallpapers around the current one and the ones on screen
+/// preloaded, and unload the rest.
+pub fn update_preloaded(
+    backend: &mut dyn Backend,
+    paths: &[PathBuf],
+    shown: &[PathBuf],
+    options: &PreloadOptions,
+) {
+    let Ok(loaded) = backend.list_loaded() else {
+        return;
+    };
+
+    let mut needed: Vec<&PathBuf> = Vec::new();
+    for path in shown {
+        if !needed.contains(&path) {
+            needed.push(path);
+        }
+    }
+    let mut total: u64 = needed.iter().map(|path| estimated_size(path)).sum();
+
+    for index in preload_order(paths.len(), options.preload_ahead, options.preload_behind) {
+        let path = &paths[index];
+        // Videos are shown as a still frame, which is preloaded once shown.
+        if needed.contains(&path) || is_video(path) {
+            continue;
+        }
+        if let Some(budget) = options.preload_budget {
+            let size = estimated_size(path);
+            if index != 0 && total + size > budget {
+                break;
+            }
+            total += size;
+        }
+        needed.push(path);
+    }
+
+    for path in needed.iter().filter(|&path| !loaded.contains(path)) {
+        if let Err(e) = backend.preload(path) {
+            eprintln!("Failed to preload needed wallpaper: {:?}", e);
+        }
+    }
+
+    for path in loaded.iter().filter(|path| !needed.contains(path)) {
+        if let Err(e) = backend.unload(path) {
+            eprintln!("Failed to unload wallpaper: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::backend::Capabilities;
+
+    #[test]
+    fn order_of_short_lists() {
+        assert!(preload_order(0, 5, 3).is_empty());
+        assert_eq!(preload_order(1, 5, 3), [0]);
+        assert_eq!(preload_order(2, 5, 3), [0, 1]);
+        assert_eq!(preload_order(2, 0, 3), [0, 1]);
+    }
+
+    #[test]
+    fn order_alternates_ahead_and_behind() {
+        assert_eq!(preload_order(10, 2, 3), [0, 1, 9, 2, 8, 7]);
+        assert_eq!(preload_order(10, 0, 0), [0]);
+    }
+
+    #[test]
+    fn window_larger_than_the_list_lists_each_index_once() {
+        assert_eq!(preload_order(4, 5, 3), [0, 1, 2, 3]);
+        assert_eq!(preload_order(4, 1, 5), [0, 1, 3, 2]);
+    }
+
+    /// Records what it is asked to preload and unload.
+    #[derive(Default)]
+    struct Recorder {
+        loaded: Vec<PathBuf>,
+        preloaded: Vec<PathBuf>,
+        unloaded: Vec<PathBuf>,
+    }
+
+    impl Backend for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities { preload: true, ..Default::default() }
+        }
+
+        fn set(&mut self, _path: &Path, _output: Option<&str>) -> Result<()> {
+            Ok(())
+        }
+
+        fn preload(&mut self, path: &Path) -> Result<()> {
+            self.preloaded.push(path.to_path_buf());
+            Ok(())
+        }
+
+        fn unload(&mut self, path: &Path) -> Result<()> {
+            self.unloaded.push(path.to_path_buf());
+            Ok(())
+        }
+
+        fn list_loaded(&mut self) -> Result<Vec<PathBuf>> {
+            Ok(self.loaded.clone())
+        }
+    }
+
+    #[test]
+    fn budget_stops_preloading() {
+        let dir = env::temp_dir().join(format!("wallpaper-manager-test-{}-preload", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Not images, so their file size counts.
+        let paths: Vec<PathBuf> = ["a", "b", "c", "d"].iter().map(|name| dir.join(format!("{}.png", name))).collect();
+        for path in &paths {
+            fs::write(path, [0; 100]).unwrap();
+        }
+
+        let mut backend = Recorder { loaded: vec![paths[3].clone()], ..Default::default() };
+        let options = PreloadOptions { preload_ahead: 3, preload_behind: 0, preload_budget: Some(250) };
+        update_preloaded(&mut backend, &paths, &paths[..1], &options);
+        assert_eq!(backend.preloaded, &paths[..2]);
+        assert_eq!(backend.unloaded, &paths[3..]);
+
+        // The wallpapers on screen and the current one are kept, even over
+        // the budget.
+        let mut backend = Recorder::default();
+        let options = PreloadOptions { preload_budget: Some(50), ..options };
+        update_preloaded(&mut backend, &paths, &paths[1..3], &options);
+        assert_eq!(backend.preloaded, [paths[1].clone(), paths[2].clone(), paths[0].clone()]);
+    }
+}
@@ -0,0 +1,194 @@
+//! CPU scaling and cropping for the backends that draw the wallpaper themselves.
+//!
+//! Everything here works on plain buffers, without a compositor.
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+
+use crate::backend::ScaleMode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The part of the image that is shown and where it is drawn on the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub src: Rect,
+    pub dst: Rect,
+}
+
+/// Compute the placement of an `image` sized picture on an `output` sized
+/// canvas. [`ScaleMode::Tile`] places a single copy at the origin.
+pub fn placement(image: (u32, u32), output: (u32, u32), mode: ScaleMode) -> Placement {
+    let (iw, ih) = image;
+    let (ow, oh) = output;
+    let full_image = Rect { x: 0, y: 0, width: iw, height: ih };
+    let full_output = Rect { x: 0, y: 0, width: ow, height: oh };
+
+    if iw == 0 || ih == 0 || ow == 0 || oh == 0 {
+        return Placement {
+            src: Rect { x: 0, y: 0, width: 0, height: 0 },
+            dst: Rect { x: 0, y: 0, width: 0, height: 0 },
+        };
+    }
+
+    match mode {
+        ScaleMode::Stretch => Placement { src: full_image, dst: full_output },
+        ScaleMode::Fill => {
+            // Crop the image to the output's aspect ratio, keeping it centered.
+            let (width, height) = if iw as u64 * oh as u64 > ow as u64 * ih as u64 {
+                (((ow as u64 * ih as u64) / oh as u64).max(1) as u32, ih)
+            } else {
+                (iw, ((oh as u64 * iw as u64) / ow as u64).max(1) as u32)
+            };
+            Placement { src: centered(width, height, iw, ih), dst: full_output }
+        }
+        ScaleMode::Fit => {
+            let (width, height) = if iw as u64 * oh as u64 > ow as u64 * ih as u64 {
+                (ow, ((ih as u64 * ow as u64) / iw as u64).max(1) as u32)
+            } else {
+                (((iw as u64 * oh as u64) / ih as u64).max(1) as u32, oh)
+            };
+            Placement { src: full_image, dst: centered(width, height, ow, oh) }
+        }
+        ScaleMode::Center => {
+            let width = iw.min(ow);
+            let height = ih.min(oh);
+            Placement {
+                src: centered(width, height, iw, ih),
+                dst: centered(width, height, ow, oh),
+            }
+        }
+        ScaleMode::Tile => {
+            let width = iw.min(ow);
+            let height = ih.min(oh);
+            Placement {
+                src: Rect { x: 0, y: 0, width, height },
+                dst: Rect { x: 0, y: 0, width, height },
+            }
+        }
+    }
+}
+
+/// A `width` x `height` rect centered in a `outer_width` x `outer_height` one.
+fn centered(width: u32, height: u32, outer_width: u32, outer_height: u32) -> Rect {
+    Rect {
+        x: (outer_width - width) / 2,
+        y: (outer_height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// Draw `image` onto a black canvas of the given size.
+pub fn compose(image: &RgbaImage, width: u32, height: u32, mode: ScaleMode) -> RgbaImage {
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    let Placement { src, dst } = placement(image.dimensions(), (width, height), mode);
+    if dst.width == 0 || dst.height == 0 {
+        return canvas;
+    }
+
+    if mode == ScaleMode::Tile {
+        for y in (0..height).step_by(image.height() as usize) {
+            for x in (0..width).step_by(image.width() as usize) {
+                imageops::replace(&mut canvas, image, x as i64, y as i64);
+            }
+        }
+        return canvas;
+    }
+
+    let cropped = imageops::crop_imm(image, src.x, src.y, src.width, src.height).to_image();
+    let scaled = if (src.width, src.height) == (dst.width, dst.height) {
+        cropped
+    } else {
+        imageops::resize(&cropped, dst.width, dst.height, FilterType::Triangle)
+    };
+    imageops::overlay(&mut canvas, &scaled, dst.x as i64, dst.y as i64);
+    canvas
+}
+
+/// Convert to the little endian `XRGB8888`/`ARGB8888` layout used by wl_shm.
+pub fn write_xrgb8888(image: &RgbaImage, out: &mut [u8]) {
+    for (pixel, chunk) in image.pixels().zip(out.chunks_exact_mut(4)) {
+        let [r, g, b, a] = pixel.0;
+        chunk.copy_from_slice(&[b, g, r, a]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    #[test]
+    fn stretch_uses_everything() {
+        for (image, output) in [((2000, 1000), (1000, 1000)), ((1000, 2000), (1920, 1080))] {
+            let placement = placement(image, output, ScaleMode::Stretch);
+            assert_eq!(placement.src, rect(0, 0, image.0, image.1));
+            assert_eq!(placement.dst, rect(0, 0, output.0, output.1));
+        }
+    }
+
+    #[test]
+    fn fill_crops_the_image_to_the_output_ratio() {
+        let landscape = placement((2000, 1000), (1000, 1000), ScaleMode::Fill);
+        assert_eq!(landscape.src, rect(500, 0, 1000, 1000));
+        assert_eq!(landscape.dst, rect(0, 0, 1000, 1000));
+
+        let portrait = placement((1000, 2000), (1920, 1080), ScaleMode::Fill);
+        assert_eq!(portrait.src, rect(0, 719, 1000, 562));
+        assert_eq!(portrait.dst, rect(0, 0, 1920, 1080));
+    }
+
+    #[test]
+    fn fit_leaves_bars_around_the_image() {
+        let landscape = placement((2000, 1000), (1000, 1000), ScaleMode::Fit);
+        assert_eq!(landscape.src, rect(0, 0, 2000, 1000));
+        assert_eq!(landscape.dst, rect(0, 250, 1000, 500));
+
+        let portrait = placement((1000, 2000), (1920, 1080), ScaleMode::Fit);
+        assert_eq!(portrait.src, rect(0, 0, 1000, 2000));
+        assert_eq!(portrait.dst, rect(690, 0, 540, 1080));
+    }
+
+    #[test]
+    fn center_crops_what_overflows() {
+        let wide = placement((3000, 500), (1920, 1080), ScaleMode::Center);
+        assert_eq!(wide.src, rect(540, 0, 1920, 500));
+        assert_eq!(wide.dst, rect(0, 290, 1920, 500));
+
+        let small = placement((100, 100), (1920, 1080), ScaleMode::Center);
+        assert_eq!(small.src, rect(0, 0, 100, 100));
+        assert_eq!(small.dst, rect(910, 490, 100, 100));
+    }
+
+    #[test]
+    fn tile_places_one_copy_at_the_origin() {
+        let placement = placement((3000, 500), (1920, 1080), ScaleMode::Tile);
+        assert_eq!(placement.src, rect(0, 0, 1920, 500));
+        assert_eq!(placement.dst, rect(0, 0, 1920, 500));
+    }
+
+    #[test]
+    fn empty_sizes_place_nothing() {
+        for mode in [ScaleMode::Stretch, ScaleMode::Fill, ScaleMode::Fit, ScaleMode::Center, ScaleMode::Tile] {
+            assert_eq!(placement((0, 1000), (1920, 1080), mode).dst, rect(0, 0, 0, 0));
+            assert_eq!(placement((1920, 1080), (1920, 0), mode).dst, rect(0, 0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn centered_rounds_down() {
+        assert_eq!(centered(3, 4, 10, 11), rect(3, 3, 3, 4));
+        assert_eq!(centered(10, 10, 10, 10), rect(0, 0, 10, 10));
+        assert_eq!(placement((1001, 1000), (1000, 1000), ScaleMode::Fill).src, rect(0, 0, 1000, 1000));
+    }
+}
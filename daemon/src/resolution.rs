@@ -0,0 +1,145 @@
+//! Rules on the size and shape of wallpapers, relative to the outputs
+//! showing them.
+
+use std::str::FromStr;
+
+use wallpaper_manager_ipc::ResolutionRules;
+
+/// Resolution rules given to `start-daemon` and `set-resolution-rules`.
+#[derive(clap::Args, Clone, Debug, Default)]
+#[command(about = None, long_about = None)]
+pub struct ResolutionOptions {
+    /// Leave out images smaller than this, e.g. "1920x1080"
+    #[clap(long, value_name = "WIDTHxHEIGHT")]
+    pub min_size: Option<Size>,
+    /// Only show an image on outputs whose aspect ratio is within this
+    /// fraction of its own, e.g. 0.1 for 10%
+    #[clap(long, value_parser = parse_tolerance)]
+    pub aspect_tolerance: Option<f32>,
+    /// Show portrait images on portrait outputs and landscape images on
+    /// landscape ones
+    #[clap(long)]
+    pub match_orientation: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(size: &str) -> Result<Self, Self::Err> {
+        let parsed = size
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+        match parsed {
+            Some((width, height)) => Ok(Size { width, height }),
+            None => Err(format!("'{}' is not a size like 1920x1080", size)),
+        }
+    }
+}
+
+/// Parse a tolerance, which can't be negative or NaN.
+fn parse_tolerance(tolerance: &str) -> Result<f32, String> {
+    let parsed: f32 = tolerance.parse().map_err(|e| format!("invalid tolerance '{}': {}", tolerance, e))?;
+    if parsed.is_nan() || parsed < 0.0 {
+        return Err(format!("the tolerance must be 0 or more, not {}", tolerance));
+    }
+    Ok(parsed)
+}
+
+impl From<ResolutionOptions> for ResolutionRules {
+    fn from(options: ResolutionOptions) -> Self {
+        ResolutionRules {
+            min_width: options.min_size.map(|size| size.width),
+            min_height: options.min_size.map(|size| size.height),
+            aspect_tolerance: options.aspect_tolerance,
+            match_orientation: options.match_orientation,
+        }
+    }
+}
+
+/// Whether an image of `size` passes `rules`, for an output of `output` size
+/// if given. Without an output, only the minimum size is checked.
+pub fn fits(rules: &ResolutionRules, (width, height): (u32, u32), output: Option<(u32, u32)>) -> bool {
+    if rules.min_width.is_some_and(|min| width < min) || rules.min_height.is_some_and(|min| height < min) {
+        return false;
+    }
+    let Some((output_width, output_height)) = output.filter(|(width, height)| *width > 0 && *height > 0) else {
+        return true;
+    };
+
+    // Square images and outputs go with both orientations.
+    if rules.match_orientation
+        && width != height
+        && output_width != output_height
+        && (width > height) != (output_width > output_height)
+    {
+        return false;
+    }
+    let ratio = width as f32 / height as f32;
+    let output_ratio = output_width as f32 / output_height as f32;
+    rules.aspect_tolerance.map_or(true, |tolerance| (ratio / output_ratio - 1.0).abs() <= tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimum_size() {
+        let rules = ResolutionRules { min_width: Some(1920), min_height: Some(1080), ..Default::default() };
+        assert!(fits(&rules, (1920, 1080), None));
+        assert!(!fits(&rules, (1919, 1080), None));
+        assert!(!fits(&rules, (1920, 1079), Some((1920, 1080))));
+        let rules = ResolutionRules { min_height: Some(1080), ..Default::default() };
+        assert!(fits(&rules, (10, 1080), None));
+    }
+
+    #[test]
+    fn orientation() {
+        let rules = ResolutionRules { match_orientation: true, ..Default::default() };
+        assert!(fits(&rules, (1920, 1080), Some((2560, 1440))));
+        assert!(!fits(&rules, (1920, 1080), Some((1080, 1920))));
+        assert!(!fits(&rules, (1080, 1920), Some((1920, 1080))));
+        // Without an output, or with one of unknown size, only the size counts.
+        assert!(fits(&rules, (1080, 1920), None));
+        assert!(fits(&rules, (1080, 1920), Some((0, 0))));
+    }
+
+    #[test]
+    fn square_images_and_outputs_match_both_orientations() {
+        let rules = ResolutionRules { match_orientation: true, ..Default::default() };
+        assert!(fits(&rules, (1000, 1000), Some((1920, 1080))));
+        assert!(fits(&rules, (1000, 1000), Some((1080, 1920))));
+        assert!(fits(&rules, (1920, 1080), Some((1000, 1000))));
+        assert!(fits(&rules, (1080, 1920), Some((1000, 1000))));
+    }
+
+    #[test]
+    fn aspect_tolerance() {
+        let rules = ResolutionRules { aspect_tolerance: Some(0.1), ..Default::default() };
+        // 16:9 on 16:10 is off by 11%.
+        assert!(!fits(&rules, (1920, 1080), Some((1920, 1200))));
+        assert!(fits(&rules, (1920, 1080), Some((2560, 1440))));
+        assert!(fits(&rules, (1920, 1080), None));
+        let rules = ResolutionRules { aspect_tolerance: Some(0.12), ..rules };
+        assert!(fits(&rules, (1920, 1080), Some((1920, 1200))));
+        let rules = ResolutionRules { aspect_tolerance: Some(0.0), ..rules };
+        assert!(fits(&rules, (3840, 2160), Some((1920, 1080))));
+        assert!(!fits(&rules, (1920, 1080), Some((1920, 1200))));
+    }
+
+    #[test]
+    fn invalid_tolerances_are_rejected() {
+        for tolerance in [-0.1, f32::NAN] {
+            let rules = ResolutionRules { aspect_tolerance: Some(tolerance), ..Default::default() };
+            assert!(rules.validate().is_err(), "{}", tolerance);
+            assert!(parse_tolerance(&tolerance.to_string()).is_err(), "{}", tolerance);
+        }
+        assert!(ResolutionRules { aspect_tolerance: Some(0.0), ..Default::default() }.validate().is_ok());
+    }
+}
@@ -0,0 +1,147 @@
+//! Finding the wallpapers in a directory tree.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+
+use crate::media::has_media_extension;
+
+/// Directory scanning settings given to `start-daemon`.
+#[derive(clap::Args, Clone, Debug)]
+#[command(about = None, long_about = None)]
+pub struct ScanOptions {
+    /// How many levels of subdirectories to look into, 0 for none
+    #[clap(long)]
+    pub max_depth: Option<usize>,
+    /// Only keep wallpapers whose path relative to their source matches one
+    /// of these globs, or regexes written "re:REGEX". Matching a directory
+    /// keeps everything in it
+    #[clap(long = "include", value_name = "PATTERN")]
+    pub include: Vec<Filter>,
+    /// Leave out wallpapers whose path relative to their source matches one
+    /// of these, e.g. "nsfw" or "*-old.*"
+    #[clap(long = "exclude", value_name = "PATTERN")]
+    pub exclude: Vec<Filter>,
+    /// Shuffle playlists given as sources instead of keeping their order
+    #[clap(long)]
+    pub shuffle_playlists: bool,
+    /// Keep the wallpapers of directories and globs in name order instead of
+    /// shuffling them
+    #[clap(long)]
+    pub ordered: bool,
+}
+
+impl ScanOptions {
+    /// Whether the filters let through a wallpaper at `relative` to its
+    /// source.
+    pub fn allows(&self, relative: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|filter| filter.matches(relative)))
+            && !self.exclude.iter().any(|filter| filter.matches(relative))
+    }
+}
+
+/// A glob, or a regex after `re:`, matched against relative paths.
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        match filter.strip_prefix("re:") {
+            Some(regex) => regex::Regex::new(regex).map(Filter::Regex).map_err(|e| e.to_string()),
+            // `nsfw/` means the directory, which is how globs are matched anyway.
+            None => glob::Pattern::new(filter.trim_end_matches('/')).map(Filter::Glob).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Glob(pattern) => write!(f, "{}", pattern),
+            Filter::Regex(regex) => write!(f, "re:{}", regex),
+        }
+    }
+}
+
+impl Filter {
+    /// Whether `relative` matches. A glob may match one of the directories
+    /// `relative` is in instead, a regex only has to match part of it.
+    pub fn matches(&self, relative: &Path) -> bool {
+        match self {
+            Filter::Glob(pattern) => relative
+                .ancestors()
+                .filter(|path| !path.as_os_str().is_empty())
+                .any(|path| pattern.matches_path(path)),
+            Filter::Regex(regex) => regex.is_match(&relative.to_string_lossy()),
+        }
+    }
+}
+
+/// List the images and videos under `dir`, in subdirectories up to
+/// `max_depth` levels deep. They are only told apart by their extension,
+/// see [`crate::media::validate`] for checking their content.
+///
+/// Symlinks are followed, a directory or file reached a second time through
+/// one is skipped. Hidden files and directories are left out.
+pub fn scan(dir: &Path, options: &ScanOptions) -> Result<Vec<PathBuf>> {
+    let root = fs::canonicalize(dir).with_context(|| format!("reading {}", dir.display()))?;
+    let mut scan = Scan { options, visited: HashSet::from([root]), files: HashSet::new(), paths: Vec::new() };
+    scan.dir(dir, 0).with_context(|| format!("reading {}", dir.display()))?;
+    Ok(scan.paths)
+}
+
+struct Scan<'a> {
+    options: &'a ScanOptions,
+    /// Canonical paths of the directories entered so far.
+    visited: HashSet<PathBuf>,
+    /// Canonical paths of the wallpapers found so far.
+    files: HashSet<PathBuf>,
+    paths: Vec<PathBuf>,
+}
+
+impl Scan<'_> {
+    fn dir(&mut self, dir: &Path, depth: usize) -> Result<()> {
+        let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .map(|entry| entry.path())
+            .collect();
+        entries.sort();
+
+        for path in entries {
+            // Follows symlinks, a broken one is skipped like any other error.
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let Ok(canonical) = fs::canonicalize(&path) else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                if self.options.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                    continue;
+                }
+                if !self.visited.insert(canonical) {
+                    println!("Skipping {}, it was already scanned", path.display());
+                    continue;
+                }
+                if let Err(e) = self.dir(&path, depth + 1) {
+                    eprintln!("Failed to read {}: {}", path.display(), e);
+                }
+            } else if metadata.is_file() && has_media_extension(&path) && self.files.insert(canonical) {
+                self.paths.push(path);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,14 @@
 use std::hash::{BuildHasher, Hasher, RandomState};
 
+/// A random index below `n`, which must not be zero.
+pub fn random_index(n: usize) -> usize {
+    (RandomState::new().build_hasher().finish() as usize) % n
+}
+
 pub fn shuffle<T>(vec: &mut [T]) {
     let n = vec.len();
-    for i in 0..(n - 1) {
-        let j = (RandomState::new().build_hasher().finish() as usize) % (n - i) + i;
+    for i in 0..n.saturating_sub(1) {
+        let j = random_index(n - i) + i;
         vec.swap(i, j);
     }
 }
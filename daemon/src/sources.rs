@@ -0,0 +1,277 @@
+//! Where wallpapers come from: directories, single files, playlists and glob
+//! patterns, each with a weight deciding its share of the rotation.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::Result;
+
+use crate::media::has_media_extension;
+use crate::playlist::{self, is_playlist};
+use crate::scan::{scan, ScanOptions};
+use crate::shuffle::shuffle;
+
+/// A directory, file, playlist or glob pattern, written `PATH` or
+/// `PATH@WEIGHT`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    pub pattern: String,
+    pub weight: u32,
+    /// Whether directories are scanned down to `--max-depth`, or only their
+    /// own files are taken.
+    pub recursive: bool,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        // Paths may contain `@` themselves, only a number after the last one
+        // is a weight.
+        let (pattern, weight) = match source.rsplit_once('@') {
+            Some((pattern, weight)) if weight.parse::<u32>().is_ok() => {
+                (pattern, weight.parse::<u32>().unwrap())
+            }
+            _ => (source, 1),
+        };
+        if pattern.is_empty() {
+            return Err(format!("'{}' has no path", source));
+        }
+        if weight == 0 {
+            return Err(format!("the weight of '{}' must be at least 1", pattern));
+        }
+        Ok(Source { pattern: pattern.to_string(), weight, recursive: true })
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.pattern, self.weight)
+    }
+}
+
+impl Source {
+    /// The pattern with a leading `~` replaced by the home directory.
+    pub fn expanded(&self) -> String {
+        match (self.pattern.strip_prefix('~'), env::var("HOME")) {
+            (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+            _ => self.pattern.clone(),
+        }
+    }
+
+    /// This source with its pattern resolved against the current directory,
+    /// so its files compare equal to the absolute paths given over IPC.
+    pub fn absolute(&self) -> Source {
+        let Ok(dir) = env::current_dir() else {
+            return self.clone();
+        };
+        // Collecting the components drops the `.` ones.
+        let path: PathBuf = dir.join(self.expanded()).components().collect();
+        Source { pattern: path.to_string_lossy().into_owned(), ..self.clone() }
+    }
+
+    /// Whether this source is a playlist file, see [`playlist::read`].
+    pub fn is_playlist(&self) -> bool {
+        let pattern = self.expanded();
+        let path = Path::new(&pattern);
+        path.is_file() && is_playlist(path)
+    }
+
+    /// `options` with the depth limited when the source isn't recursive.
+    fn scan_options(&self, options: &ScanOptions) -> ScanOptions {
+        let mut options = options.clone();
+        if !self.recursive {
+            options.max_depth = Some(0);
+        }
+        options
+    }
+
+    /// The wallpapers this source stands for, without the ones filtered out.
+    /// Directories are scanned, including the ones matched by a glob.
+    pub fn files(&self, options: &ScanOptions) -> Result<Vec<PathBuf>> {
+        let mut files = self.unfiltered_files(&self.scan_options(options))?;
+        let root = self.root();
+        files.retain(|path| path.strip_prefix(&root).map_or(true, |relative| options.allows(relative)));
+        Ok(files)
+    }
+
+    fn unfiltered_files(&self, options: &ScanOptions) -> Result<Vec<PathBuf>> {
+        let pattern = self.expanded();
+        let path = Path::new(&pattern);
+        if path.is_dir() {
+            return scan(path, options);
+        }
+        if self.is_playlist() {
+            return playlist::read(path);
+        }
+        if path.is_file() {
+            if !has_media_extension(path) {
+                bail!("{} is not a supported image or video", path.display());
+            }
+            return Ok(vec![path.to_path_buf()]);
+        }
+
+        // `dir/**` matches both a directory and the files in it.
+        let mut files = Vec::new();
+        let mut seen = HashSet::new();
+        for entry in glob::glob(&pattern).with_context(|| format!("invalid pattern '{}'", pattern))? {
+            let Ok(path) = entry else {
+                continue;
+            };
+            let found = if path.is_dir() {
+                scan(&path, options)?
+            } else if has_media_extension(&path) {
+                vec![path]
+            } else {
+                continue;
+            };
+            files.extend(found.into_iter().filter(|path| seen.insert(path.clone())));
+        }
+        if files.is_empty() && !pattern.contains(['*', '?', '[']) {
+            bail!("{} does not exist", pattern);
+        }
+        Ok(files)
+    }
+
+    /// Whether `path` is one of the files this source stands for, judging by
+    /// its location and the filters only.
+    pub fn contains(&self, path: &Path, options: &ScanOptions) -> bool {
+        path.strip_prefix(self.root()).is_ok_and(|relative| options.allows(relative))
+            && self.matches(path, &self.scan_options(options))
+    }
+
+    fn matches(&self, path: &Path, options: &ScanOptions) -> bool {
+        let pattern = self.expanded();
+        let source = Path::new(&pattern);
+        if source.is_dir() {
+            return in_tree(source, path, options);
+        }
+        if source.is_file() {
+            return source == path;
+        }
+        let Ok(pattern) = glob::Pattern::new(&pattern) else {
+            return false;
+        };
+        pattern.matches_path(path)
+            || path.ancestors().skip(1).any(|dir| pattern.matches_path(dir) && in_tree(dir, path, options))
+    }
+
+    /// The directory filters match paths relative to: the directory itself,
+    /// the one a file is in, or the part of a glob before the first wildcard.
+    pub fn root(&self) -> PathBuf {
+        let pattern = self.expanded();
+        let path = Path::new(&pattern);
+        if path.is_dir() {
+            return path.to_path_buf();
+        }
+        if path.is_file() {
+            return path.parent().unwrap_or(path).to_path_buf();
+        }
+        path.components()
+            .take_while(|component| !component.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+            .collect()
+    }
+
+    /// The directory to watch for changes to this source, with how many
+    /// levels of subdirectories below it, `None` for all of them.
+    pub fn watch_root(&self, options: &ScanOptions) -> Option<(PathBuf, Option<usize>)> {
+        let pattern = self.expanded();
+        let path = Path::new(&pattern);
+        let root = self.root();
+        if path.is_dir() {
+            Some((root, self.scan_options(options).max_depth))
+        } else if path.is_file() {
+            Some((root, Some(0)))
+        } else {
+            (root.as_os_str().is_empty() || root.is_dir()).then_some((root, None))
+        }
+    }
+}
+
+/// Whether `path` is below `dir`, not hidden and within the depth `scan`
+/// would look into.
+fn in_tree(dir: &Path, path: &Path, options: &ScanOptions) -> bool {
+    let Ok(relative) = path.strip_prefix(dir) else {
+        return false;
+    };
+    let names: Vec<_> = relative.iter().collect();
+    !names.is_empty()
+        && names.iter().all(|name| !name.to_string_lossy().starts_with('.'))
+        && options.max_depth.map_or(true, |max_depth| names.len() - 1 <= max_depth)
+}
+
+/// The wallpapers of all `sources`, shuffled unless they come from a
+/// playlist or are `ordered`, and interleaved by weight. Each file is listed
+/// once, `origins` gets the pattern of the source it came from.
+///
+/// The weights only set the initial order, the share of each source in the
+/// rotation is kept when picking the next wallpaper.
+///
+/// A source that can't be read is skipped, unless none of them can.
+pub fn collect(sources: &[Source], options: &ScanOptions, origins: &mut HashMap<PathBuf, String>) -> Result<Vec<PathBuf>> {
+    let mut lists: Vec<(&Source, Vec<PathBuf>)> = Vec::new();
+    let mut last_error = None;
+    for source in sources {
+        match source.files(options) {
+            Ok(files) if files.is_empty() => println!("No wallpapers found in {}", source.pattern),
+            Ok(mut files) => {
+                let shuffled = if source.is_playlist() { options.shuffle_playlists } else { !options.ordered };
+                if shuffled {
+                    shuffle(&mut files);
+                }
+                lists.push((source, files));
+            }
+            Err(e) => {
+                eprintln!("Failed to read {}: {:?}", source.pattern, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) if lists.is_empty() => return Err(e),
+        _ => {}
+    }
+
+    let weighted: Vec<(u32, &[PathBuf])> = lists.iter().map(|(source, files)| (source.weight, files.as_slice())).collect();
+    let mut paths = Vec::new();
+    for (path, list) in interleave(&weighted) {
+        origins.insert(path.clone(), lists[list].0.pattern.clone());
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Merge `lists` by smooth weighted round-robin, taking the entries of each
+/// in order until it runs out. A file listed more than once, in one list or
+/// several, is only taken the first time, so the queue holds each file once.
+/// Returns each file with the index of its list.
+pub fn interleave(lists: &[(u32, &[PathBuf])]) -> Vec<(PathBuf, usize)> {
+    let mut credits = vec![0i64; lists.len()];
+    let mut next = vec![0usize; lists.len()];
+    let mut seen = HashSet::new();
+    let mut merged = Vec::new();
+    loop {
+        let left: Vec<usize> = (0..lists.len()).filter(|&index| next[index] < lists[index].1.len()).collect();
+        if left.is_empty() {
+            return merged;
+        }
+        let mut total = 0;
+        for &index in &left {
+            credits[index] += lists[index].0 as i64;
+            total += lists[index].0 as i64;
+        }
+        // The first of the lists with the most credit.
+        let index = *left.iter().max_by_key(|&&index| (credits[index], -(index as i64))).unwrap();
+        credits[index] -= total;
+
+        let path = &lists[index].1[next[index]];
+        next[index] += 1;
+        if seen.insert(path.clone()) {
+            merged.push((path.clone(), index));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Spanning a single image across all outputs.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{ensure, WrapErr};
+use color_eyre::Result;
+use image::{imageops, ImageFormat};
+use xdg::BaseDirectories;
+
+use crate::outputs::OutputInfo;
+use crate::render::Rect;
+
+/// The bounding box of `outputs`, as the smallest and largest coordinates.
+fn bounds(outputs: &[OutputInfo]) -> (i32, i32, i32, i32) {
+    let min_x = outputs.iter().map(|o| o.x).min().unwrap_or(0);
+    let min_y = outputs.iter().map(|o| o.y).min().unwrap_or(0);
+    let max_x = outputs.iter().map(|o| o.x + o.width as i32).max().unwrap_or(0);
+    let max_y = outputs.iter().map(|o| o.y + o.height as i32).max().unwrap_or(0);
+    (min_x, min_y, max_x, max_y)
+}
+
+/// The size of the bounding box of `outputs`, which a spanned wallpaper
+/// covers.
+pub fn layout_size(outputs: &[OutputInfo]) -> (u32, u32) {
+    let (min_x, min_y, max_x, max_y) = bounds(outputs);
+    ((max_x - min_x) as u32, (max_y - min_y) as u32)
+}
+
+/// The part of an `image` sized picture each output shows when the picture
+/// covers the bounding box of all outputs, centered and keeping its aspect
+/// ratio. Returned in the same order as `outputs`.
+pub fn crop_rects(image: (u32, u32), outputs: &[OutputInfo]) -> Vec<Rect> {
+    if outputs.is_empty() || image.0 == 0 || image.1 == 0 {
+        return Vec::new();
+    }
+
+    let (iw, ih) = (image.0 as f64, image.1 as f64);
+    let (min_x, min_y, max_x, max_y) = bounds(outputs);
+    let (width, height) = (((max_x - min_x) as f64).max(1.0), ((max_y - min_y) as f64).max(1.0));
+
+    // Layout pixels per image pixel, and where the layout starts in the
+    // scaled image.
+    let scale = f64::max(width / iw, height / ih);
+    let offset_x = (iw * scale - width) / 2.0;
+    let offset_y = (ih * scale - height) / 2.0;
+
+    outputs
+        .iter()
+        .map(|o| {
+            let x = (((o.x - min_x) as f64 + offset_x) / scale).round() as u32;
+            let y = (((o.y - min_y) as f64 + offset_y) / scale).round() as u32;
+            let x = x.min(image.0.saturating_sub(1));
+            let y = y.min(image.1.saturating_sub(1));
+            Rect {
+                x,
+                y,
+                width: ((o.width as f64 / scale).round() as u32).clamp(1, image.0 - x),
+                height: ((o.height as f64 / scale).round() as u32).clamp(1, image.1 - y),
+            }
+        })
+        .collect()
+}
+
+/// Split `path` into one image per output, stored in the XDG cache directory.
+/// Crops from an earlier call with the same image and layout are reused.
+pub fn write_crops(path: &Path, outputs: &[OutputInfo]) -> Result<Vec<(String, PathBuf)>> {
+    let xdg_dirs = BaseDirectories::with_prefix("wallpaper-manager")?;
+    let cache_dir = xdg_dirs.create_cache_directory("span")?;
+
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    fs::metadata(path)?.modified()?.hash(&mut hasher);
+    for output in outputs {
+        (&output.name, output.x, output.y, output.width, output.height).hash(&mut hasher);
+    }
+    let key = hasher.finish();
+
+    let crops: Vec<(String, PathBuf)> = outputs
+        .iter()
+        .map(|o| (o.name.clone(), cache_dir.join(format!("{:016x}-{}.png", key, o.name))))
+        .collect();
+
+    if !crops.iter().all(|(_, crop)| crop.exists()) {
+        let image = image::open(path)
+            .with_context(|| format!("decoding {}", path.display()))?
+            .into_rgba8();
+        ensure!(image.width() > 0 && image.height() > 0, "{} is empty", path.display());
+
+        for ((_, crop), rect) in crops.iter().zip(crop_rects(image.dimensions(), outputs)) {
+            imageops::crop_imm(&image, rect.x, rect.y, rect.width, rect.height)
+                .to_image()
+                .save_with_format(crop, ImageFormat::Png)
+                .with_context(|| format!("writing {}", crop.display()))?;
+        }
+    }
+
+    // Only the crops of the current wallpaper are worth keeping.
+    for entry in fs::read_dir(&cache_dir)?.flatten() {
+        if !crops.iter().any(|(_, crop)| crop == &entry.path()) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+
+    Ok(crops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &str, x: i32, y: i32, width: u32, height: u32) -> OutputInfo {
+        OutputInfo { name: name.to_string(), x, y, width, height }
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    #[test]
+    fn side_by_side_outputs_split_the_width() {
+        let outputs = [output("A", 0, 0, 1920, 1080), output("B", 1920, 0, 1920, 1080)];
+        assert_eq!(layout_size(&outputs), (3840, 1080));
+        assert_eq!(crop_rects((3840, 1080), &outputs), [rect(0, 0, 1920, 1080), rect(1920, 0, 1920, 1080)]);
+        // A smaller image with the same ratio is scaled up.
+        assert_eq!(crop_rects((1920, 540), &outputs), [rect(0, 0, 960, 540), rect(960, 0, 960, 540)]);
+    }
+
+    #[test]
+    fn stacked_outputs_split_the_height() {
+        let outputs = [output("A", 0, 0, 1000, 500), output("B", 0, 500, 1000, 500)];
+        assert_eq!(layout_size(&outputs), (1000, 1000));
+        assert_eq!(crop_rects((1000, 1000), &outputs), [rect(0, 0, 1000, 500), rect(0, 500, 1000, 500)]);
+    }
+
+    #[test]
+    fn negative_offsets_start_at_the_left_edge() {
+        let outputs = [output("A", -1920, 0, 1920, 1080), output("B", 0, 0, 1920, 1080)];
+        assert_eq!(layout_size(&outputs), (3840, 1080));
+        assert_eq!(crop_rects((3840, 1080), &outputs), [rect(0, 0, 1920, 1080), rect(1920, 0, 1920, 1080)]);
+    }
+
+    #[test]
+    fn narrow_image_is_cropped_vertically() {
+        let outputs = [output("A", 0, 0, 1920, 1080), output("B", 1920, 0, 1920, 1080)];
+        assert_eq!(crop_rects((1920, 1080), &outputs), [rect(0, 270, 960, 540), rect(960, 270, 960, 540)]);
+    }
+
+    #[test]
+    fn nothing_to_crop() {
+        assert!(crop_rects((1920, 1080), &[]).is_empty());
+        assert!(crop_rects((0, 1080), &[output("A", 0, 0, 1920, 1080)]).is_empty());
+    }
+}
@@ -1,16 +1,55 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::mem;
 use std::option::Option;
 use std::time::{Instant, Duration};
-use std::path::PathBuf;
-use std::process::Command;
-use std::str;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
 
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
+use smithay_client_toolkit::reexports::calloop::channel::{self, Channel};
+use smithay_client_toolkit::reexports::calloop::ping::Ping;
+use wallpaper_manager_ipc::{IpcError, ResolutionRules, Transition};
+
+use crate::backend::{
+    Backend, BackendOptions, Capabilities, CommandBackend, Gnome, Hyprpaper, Kde, LayerShellBackend, Mpvpaper,
+    Swaybg, Swww, Xfce, X11,
+};
+use crate::backend_thread::{self, Change, Job, Outcome, Report, Task};
+use crate::ipc_server::{Deferred, PendingReply};
+use crate::collections::{Collection, Saved, DEFAULT_COLLECTION};
+use crate::detect::detect_backend;
+use crate::media::{self, has_media_extension, is_video, media_kind, validate, MediaFallback, MediaKind};
+use crate::outputs::OutputInfo;
+use crate::playlist;
+use crate::preload::PreloadOptions;
+use crate::resolution::fits;
+use crate::scan::{scan, Filter, ScanOptions};
+use crate::shuffle::random_index;
+use crate::sources::{collect, Source};
+use crate::span;
+use crate::watcher::Watcher;
 
 pub struct WallpaperManager {
-    #[allow(dead_code)]
-    pub dir: PathBuf,
+    pub sources: Vec<Source>,
+    pub scan_options: ScanOptions,
+    /// Watches the sources added over IPC, set once the event loop runs.
+    pub watcher: Option<Watcher>,
+    /// Sets the rotation timer again, after the interval changed.
+    pub rearm: Option<Ping>,
     pub interval: Duration,
-    pub wallpaper_daemon: WallpaperDaemon,
+    pub backend: Arc<Mutex<Box<dyn Backend>>>,
+    /// Hands changes to the backend thread, see [`Self::spawn_backend_thread`].
+    jobs: Option<mpsc::Sender<Job>>,
+    /// Id of the next job for the backend thread.
+    pub(crate) next_job: u64,
+    /// IPC replies waiting for the backend thread.
+    pub(crate) replies: Vec<PendingReply>,
+    /// IPC requests waiting for the outputs to be listed.
+    pub(crate) deferred: Vec<Deferred>,
+    /// Name and capabilities of the active backend, as of the last change.
+    pub backend_name: &'static str,
+    pub capabilities: Capabilities,
     pub socket_path: PathBuf,
     pub is_paused: bool,
     pub last_update: Option<Instant>,
@@ -18,20 +57,76 @@ pub struct WallpaperManager {
     pub last_resume: Option<Instant>,
     pub paths: Vec<PathBuf>,
     pub waiting_after_pause: bool,
+    /// Set once a wallpaper was sent to the backend outside the rotation, so
+    /// that the next one gets a full interval.
     pub skip_after_manual: bool,
+    pub last_error: Option<IpcError>,
+    pub rotation: Rotation,
+    pub outputs: BTreeMap<String, OutputWallpaper>,
+    pub preload: PreloadOptions,
+    pub media_fallback: MediaFallback,
+    /// Why `--wallpaper-daemon auto` picked the backend.
+    pub backend_reason: Option<String>,
+    /// Names of the backends in order of preference, more than one when
+    /// falling back.
+    pub backend_chain: Vec<String>,
+    /// Name of the active collection, whose sources are `sources`.
+    pub collection: String,
+    /// The other collections.
+    pub collections: BTreeMap<String, Collection>,
+    /// Files left out of the queue, with why they failed [`validate`].
+    pub quarantine: BTreeMap<PathBuf, String>,
+    /// Image sizes read by [`validate`].
+    pub dimensions: HashMap<PathBuf, (u32, u32)>,
+    /// Whether each wallpaper is a still image, an animated one or a video,
+    /// found when it is validated.
+    pub kinds: HashMap<PathBuf, MediaKind>,
+    /// Pattern of the source each wallpaper came from, which decides its
+    /// share of the rotation.
+    pub origins: HashMap<PathBuf, String>,
+    /// Smooth weighted round-robin credit of each source pattern.
+    credits: HashMap<String, i64>,
+    /// The wallpaper last picked from each source, the next one from it
+    /// comes after.
+    last_picked: HashMap<String, PathBuf>,
+    pub resolution_rules: ResolutionRules,
+}
+
+/// The wallpaper shown on a single output.
+#[derive(Clone, Debug)]
+pub struct OutputWallpaper {
+    pub info: OutputInfo,
+    /// Position in `paths`, relative to the first entry.
+    pub offset: usize,
+    pub current: Option<PathBuf>,
+    /// The file given to the backend, which differs from `current` when the
+    /// wallpaper is spanned.
+    pub shown: Option<PathBuf>,
 }
 
 impl WallpaperManager {
     pub fn new(
-        dir: PathBuf,
+        sources: Vec<Source>,
+        scan_options: ScanOptions,
         interval: Duration,
-        wallpaper_daemon: WallpaperDaemon,
+        backend: Box<dyn Backend>,
         socket_path: PathBuf,
+        rotation: Rotation,
+        preload: PreloadOptions,
     ) -> Result<Self> {
         Ok(Self {
-            dir,
+            sources,
+            scan_options,
+            watcher: None,
+            rearm: None,
             interval,
-            wallpaper_daemon,
+            backend_name: backend.name(),
+            capabilities: backend.capabilities(),
+            backend: Arc::new(Mutex::new(backend)),
+            jobs: None,
+            next_job: 0,
+            replies: Vec::new(),
+            deferred: Vec::new(),
             socket_path,
             is_paused: false,
             last_update: None,
@@ -40,110 +135,1035 @@ impl WallpaperManager {
             paths: Vec::new(),
             waiting_after_pause: false,
             skip_after_manual: false,
+            last_error: None,
+            rotation,
+            outputs: BTreeMap::new(),
+            preload,
+            media_fallback: MediaFallback::default(),
+            backend_reason: None,
+            backend_chain: Vec::new(),
+            collection: DEFAULT_COLLECTION.to_string(),
+            collections: BTreeMap::new(),
+            quarantine: BTreeMap::new(),
+            dimensions: HashMap::new(),
+            kinds: HashMap::new(),
+            origins: HashMap::new(),
+            credits: HashMap::new(),
+            last_picked: HashMap::new(),
+            resolution_rules: ResolutionRules::default(),
         })
     }
 
-    pub fn set_wallpaper(&mut self, path: PathBuf) -> Result<()> {
-        self.last_update = Some(Instant::now());
+    /// Ask the backend for the outputs, keeping the state of known ones. Only
+    /// for startup, the backend thread lists them afterwards.
+    pub fn refresh_outputs(&mut self) -> Result<()> {
+        let outputs = self.backend.lock().unwrap().outputs()?;
+        self.update_outputs(outputs);
+        Ok(())
+    }
 
-        let daemon = self.wallpaper_daemon.clone();
-        let paths = self.paths.clone();
+    fn update_outputs(&mut self, outputs: Vec<OutputInfo>) {
+        self.outputs.retain(|name, _| outputs.iter().any(|output| &output.name == name));
 
-        std::thread::spawn(move || {
-            match daemon {
-                WallpaperDaemon::Swww => {
-                    if let Err(e) = Command::new("swww").arg("img").arg(&path).output() {
-                        eprintln!("Failed to execute 'swww img': {:?}", e);
+        for (index, output) in outputs.into_iter().enumerate() {
+            let offset = match self.rotation {
+                Rotation::Synchronized | Rotation::Span => 0,
+                Rotation::Independent => index,
+            };
+            self.outputs
+                .entry(output.name.clone())
+                .and_modify(|state| state.info = output.clone())
+                .or_insert(OutputWallpaper { info: output, offset, current: None, shown: None });
+        }
+    }
+
+    /// The state of output `name`. Requests naming an unknown output list the
+    /// outputs again before getting here, see `ipc_server`.
+    fn output_mut(&mut self, name: &str) -> Result<&mut OutputWallpaper, IpcError> {
+        self.outputs
+            .get_mut(name)
+            .ok_or_else(|| IpcError::UnknownOutput { output: name.to_string() })
+    }
+
+    /// Show the wallpaper at each output's position, or the first one after
+    /// it that can be shown there, see [`Self::first_fitting`]. With
+    /// synchronized rotation every output is brought back to the first
+    /// entry, when spanning the first entry is split across the outputs.
+    pub fn show_current(&mut self) -> Result<(), IpcError> {
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+        let len = self.paths.len();
+
+        if self.rotation != Rotation::Independent || self.outputs.is_empty() {
+            let Some(index) = self.first_fitting(0..len, None) else {
+                println!("None of the wallpapers can be shown, see the resolution rules");
+                return Ok(());
+            };
+            self.paths.rotate_left(index);
+            if self.rotation == Rotation::Span && self.outputs.len() > 1 {
+                return self.show_spanned(self.paths[0].clone());
+            }
+            for output in self.outputs.values_mut() {
+                output.offset = 0;
+            }
+            return self.set_wallpaper(self.paths[0].clone(), None);
+        }
+
+        let names: Vec<String> = self.outputs.keys().cloned().collect();
+        let mut result = Ok(());
+        for name in names {
+            let offset = self.outputs[&name].offset;
+            let Some(index) = self.first_fitting((0..len).map(|step| (offset + step) % len), Some(&name)) else {
+                println!("None of the wallpapers can be shown on {}, see the resolution rules", name);
+                continue;
+            };
+            self.outputs.get_mut(&name).unwrap().offset = index;
+            let set = self.set_wallpaper(self.paths[index].clone(), Some(&name));
+            if result.is_ok() {
+                result = set;
+            }
+        }
+        result
+    }
+
+    /// Move to the next or previous wallpaper, on `output` only if given and
+    /// the outputs rotate independently. Independent outputs all move when
+    /// none is given, each to the next wallpaper that fits it.
+    pub fn step(&mut self, output: Option<&str>, forward: bool) -> Result<(), IpcError> {
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+
+        match self.independent(output)? {
+            Some(name) => self.step_output(name, forward),
+            None if self.rotation == Rotation::Independent && !self.outputs.is_empty() => {
+                let names: Vec<String> = self.outputs.keys().cloned().collect();
+                let mut result = Ok(());
+                for name in names {
+                    let set = self.step_output(&name, forward);
+                    if result.is_ok() {
+                        result = set;
                     }
-                    println!("Wallpaper {}", &path.display());
                 }
-                WallpaperDaemon::Hyprpaper => {
-                    if let Err(e) = hyprpaper_preload(&path.to_string_lossy()) {
-                        eprintln!("Failed to preload wallpaper: {:?}", e);
-                    }
+                result
+            }
+            None => {
+                let Some(index) = self.next_index(0, forward, None) else {
+                    return Ok(());
+                };
+                self.paths.rotate_left(index);
+                self.show_current()
+            }
+        }
+    }
 
-                    if let Err(e) = hyprpaper_wallpaper(&path.to_string_lossy()) {
-                        eprintln!("Failed to set wallpaper: {:?}", e);
-                    }
+    fn step_output(&mut self, name: &str, forward: bool) -> Result<(), IpcError> {
+        let offset = self.output_mut(name)?.offset;
+        let Some(offset) = self.next_index(offset, forward, Some(name)) else {
+            return Ok(());
+        };
+        self.output_mut(name)?.offset = offset;
+        let path = self.paths[offset].clone();
+        self.set_wallpaper(path, Some(name))
+    }
 
-                    if let Ok(loaded) = hyprpaper_get_loaded() {
-                        let indices = [ 0, 1, paths.len() - 1, 2, 3, paths.len() - 2, 4, paths.len() - 3, 5 ];
+    /// `output` if it moves on its own, or `None` when the outputs move
+    /// together. An unknown output is an error either way.
+    fn independent<'a>(&mut self, output: Option<&'a str>) -> Result<Option<&'a str>, IpcError> {
+        match output {
+            Some(name) if self.rotation != Rotation::Independent => {
+                self.output_mut(name)?;
+                Ok(None)
+            }
+            output => Ok(output),
+        }
+    }
 
-                        let needed: Vec<String> = indices
-                            .iter()
-                            .filter_map(|&i| paths.get(i))
-                            .map(|p| p.to_string_lossy().to_string())
-                            .collect();
+    /// The position of the wallpaper after or before `from` to show on
+    /// `output`, or on all outputs, see [`Self::first_fitting`]. `None` when
+    /// there is none.
+    ///
+    /// Going forward, the next wallpaper comes from the source picked by
+    /// [`Self::pick_origin`] if it has one that can be shown, after the one
+    /// last picked from it.
+    fn next_index(&mut self, from: usize, forward: bool, output: Option<&str>) -> Option<usize> {
+        let origin = if forward { self.pick_origin() } else { None };
+        let len = self.paths.len();
+        let candidates = (1..=len).map(|step| if forward { (from + step) % len } else { (from + len - step) % len });
+        let last = origin.as_ref().and_then(|origin| self.last_picked.get(origin));
+        let start = last.and_then(|last| self.paths.iter().position(|path| path == last)).unwrap_or(from);
+        let from_origin = (1..=len)
+            .map(|step| (start + step) % len)
+            .filter(|&index| origin.is_some() && self.origins.get(&self.paths[index]) == origin.as_ref());
 
-                        for path in needed.iter().filter(|&path| !loaded.contains(path)) {
-                            if let Err(e) = hyprpaper_preload(path) {
-                                eprintln!("Failed to preload needed wallpaper: {:?}", e);
-                            }
-                        }
+        let index = self.first_fitting(from_origin.chain(candidates), output)?;
+        if forward {
+            if let Some(origin) = self.origins.get(&self.paths[index]) {
+                self.last_picked.insert(origin.clone(), self.paths[index].clone());
+            }
+        }
+        Some(index)
+    }
 
-                        for path in loaded.iter().filter(|path| !needed.contains(path)) {
-                            if let Err(e) = hyprpaper_unload(path) {
-                                eprintln!("Failed to unload wallpaper: {:?}", e);
-                            }
-                        }
+    /// The first of `candidates` the backend can show that follows the
+    /// resolution rules on `output`, or on all outputs. When none does, the
+    /// orientation and aspect ratio are let go rather than showing nothing
+    /// new, but never the minimum size.
+    fn first_fitting(&self, candidates: impl Iterator<Item = usize> + Clone, output: Option<&str>) -> Option<usize> {
+        let shown = |index: &usize| !self.skipped(&self.paths[*index]);
+        candidates
+            .clone()
+            .filter(shown)
+            .find(|&index| self.fits(&self.paths[index], output))
+            .or_else(|| candidates.filter(shown).find(|&index| self.big_enough(&self.paths[index])))
+    }
+
+    /// The source the next wallpaper comes from, by smooth weighted
+    /// round-robin over the sources in the queue, so each gets a share of the
+    /// rotation proportional to its weight however many files it has. `None`
+    /// when there is only one.
+    fn pick_origin(&mut self) -> Option<String> {
+        let present: BTreeSet<&String> = self.paths.iter().filter_map(|path| self.origins.get(path)).collect();
+        if present.len() < 2 {
+            return None;
+        }
+        // Sources removed since keep a weight of 1 until the next rebuild.
+        let weight = |pattern: &str| {
+            self.sources.iter().find(|source| source.pattern == pattern).map_or(1, |source| source.weight) as i64
+        };
+
+        let mut total = 0;
+        let mut picked: Option<(&String, i64)> = None;
+        for pattern in present {
+            let credit = self.credits.entry(pattern.clone()).or_insert(0);
+            *credit += weight(pattern);
+            total += weight(pattern);
+            if picked.map_or(true, |(_, most)| *credit > most) {
+                picked = Some((pattern, *credit));
+            }
+        }
+        let (pattern, _) = picked?;
+        *self.credits.get_mut(pattern).unwrap() -= total;
+        Some(pattern.clone())
+    }
+
+    /// The kind of `path`, from the one found by `check` when there is one.
+    fn kind(&self, path: &Path) -> MediaKind {
+        self.kinds.get(path).copied().unwrap_or_else(|| media_kind(path))
+    }
+
+    /// Whether `path` is left out of the rotation, because the backend can't
+    /// show it and `--media-fallback skip` is set.
+    fn skipped(&self, path: &Path) -> bool {
+        self.media_fallback == MediaFallback::Skip
+            && !self.kind(path).supported_by(self.capabilities)
+    }
+
+    /// Whether `path` follows the resolution rules on `output`, or on every
+    /// output when not given. Spanned wallpapers are compared to the whole
+    /// layout. Videos, whose size isn't known, always do.
+    fn fits(&self, path: &Path, output: Option<&str>) -> bool {
+        let Some(&size) = self.dimensions.get(path) else {
+            return true;
+        };
+        let outputs: Vec<(u32, u32)> = match output {
+            Some(name) => self.outputs.get(name).map(|o| (o.info.width, o.info.height)).into_iter().collect(),
+            None if self.rotation == Rotation::Span && self.outputs.len() > 1 => {
+                let infos: Vec<OutputInfo> = self.outputs.values().map(|o| o.info.clone()).collect();
+                vec![span::layout_size(&infos)]
+            }
+            None => self.outputs.values().map(|o| (o.info.width, o.info.height)).collect(),
+        };
+        if outputs.is_empty() {
+            return fits(&self.resolution_rules, size, None);
+        }
+        outputs.into_iter().all(|output| fits(&self.resolution_rules, size, Some(output)))
+    }
+
+    /// Whether `path` is at least the minimum size, the one rule that is
+    /// always kept.
+    fn big_enough(&self, path: &Path) -> bool {
+        self.dimensions.get(path).map_or(true, |&size| fits(&self.resolution_rules, size, None))
+    }
+
+    /// Jump to `path`, on `output` only if given and the outputs rotate
+    /// independently. It must follow the resolution rules.
+    pub fn go_to(&mut self, path: PathBuf, output: Option<&str>) -> Result<(), IpcError> {
+        let Some(index) = self.paths.iter().position(|x| x == &path) else {
+            return Err(IpcError::PathNotAdded { path });
+        };
+        let output = self.independent(output)?;
+        if !self.fits(&path, output) {
+            return Err(IpcError::InvalidPath { path, message: "does not follow the resolution rules".to_string() });
+        }
+
+        match output {
+            None => {
+                self.paths.rotate_left(index);
+                self.show_current()
+            }
+            Some(name) => {
+                self.output_mut(name)?.offset = index;
+                self.set_wallpaper(path, Some(name))
+            }
+        }
+    }
+
+    /// Queue the wallpapers at `path`, a file or directory that appeared in
+    /// one of the sources, each at a random position.
+    /// A playlist that changed is read again, with the other sources.
+    pub fn add_found(&mut self, path: &Path) {
+        if self.sources.iter().any(|source| source.is_playlist() && Path::new(&source.expanded()) == path) {
+            println!("Reloading {}", path.display());
+            if let Err(e) = self.rebuild() {
+                eprintln!("Failed to reload the wallpapers: {:?}", e);
+            }
+            return;
+        }
+
+        let mut files = if path.is_dir() {
+            scan(path, &self.scan_options).unwrap_or_else(|e| {
+                eprintln!("Failed to read {}: {:?}", path.display(), e);
+                Vec::new()
+            })
+        } else if has_media_extension(path) {
+            vec![path.to_path_buf()]
+        } else {
+            Vec::new()
+        };
+
+        files.retain(|file| match self.sources.iter().find(|source| source.contains(file, &self.scan_options)) {
+            Some(source) => {
+                self.origins.entry(file.clone()).or_insert_with(|| source.pattern.clone());
+                true
+            }
+            None => false,
+        });
+        for file in files {
+            if self.check(&file) {
+                if !self.paths.contains(&file) {
+                    println!("Adding {}", file.display());
+                    self.insert_path(None, file);
+                }
+            } else if self.paths.contains(&file) {
+                // A queued wallpaper rewritten with bad content leaves the
+                // queue, and stays in the quarantine.
+                let reason = self.quarantine.remove(&file);
+                self.remove_path(&file);
+                self.quarantine.extend(reason.map(|reason| (file, reason)));
+            }
+        }
+    }
+
+    /// `paths` without the files failing [`validate`], which are quarantined
+    /// instead.
+    pub fn validated(&mut self, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        paths.into_iter().filter(|path| self.check(path)).collect()
+    }
+
+    /// Validate `path`, moving it in or out of the quarantine.
+    fn check(&mut self, path: &Path) -> bool {
+        match validate(path) {
+            Ok(size) => {
+                self.quarantine.remove(path);
+                match size {
+                    Some(size) => self.dimensions.insert(path.to_path_buf(), size),
+                    None => self.dimensions.remove(path),
+                };
+                self.kinds.insert(path.to_path_buf(), media_kind(path));
+                true
+            }
+            Err(reason) => {
+                if self.quarantine.get(path) != Some(&reason) {
+                    eprintln!("Leaving out {}: {}", path.display(), reason);
+                }
+                self.quarantine.insert(path.to_path_buf(), reason);
+                false
+            }
+        }
+    }
+
+    /// Put `path` at `index` in the queue, or at a random position, keeping
+    /// the wallpaper of each output. Returns where it ended up.
+    fn insert_path(&mut self, index: Option<usize>, path: PathBuf) -> usize {
+        let index = match index {
+            Some(index) => index.min(self.paths.len()),
+            None if self.paths.is_empty() => 0,
+            // Never in front of the first entry, the one synchronized outputs
+            // are showing.
+            None => random_index(self.paths.len()) + 1,
+        };
+        if !self.paths.is_empty() {
+            for output in self.outputs.values_mut() {
+                if output.offset >= index {
+                    output.offset += 1;
+                }
+            }
+        }
+        self.paths.insert(index, path);
+        index
+    }
+
+    /// Read wallpapers from `source` from now on, and watch it for changes.
+    fn add_source(&mut self, source: Source) {
+        if let (Some(watcher), Some((dir, depth))) = (&self.watcher, source.watch_root(&self.scan_options)) {
+            watcher.watch(&dir, depth);
+        }
+        self.sources.retain(|known| known.pattern != source.pattern);
+        self.sources.push(source);
+    }
+
+    /// Add the image or video at `path` to the sources and queue it at
+    /// `position`, or at a random one.
+    pub fn add_wallpaper(&mut self, path: PathBuf, position: Option<usize>) -> Result<(), IpcError> {
+        if !path.is_file() || !has_media_extension(&path) {
+            return Err(IpcError::InvalidPath { path, message: "not a supported image or video".to_string() });
+        }
+        if !self.check(&path) {
+            let message = self.quarantine[&path].clone();
+            return Err(IpcError::InvalidPath { path, message });
+        }
+        if self.paths.contains(&path) {
+            return Err(IpcError::InvalidPath { path, message: "already added".to_string() });
+        }
+
+        let source = Source { pattern: path.to_string_lossy().into_owned(), weight: 1, recursive: true };
+        self.origins.insert(path.clone(), source.pattern.clone());
+        self.add_source(source);
+        println!("Adding {}", path.display());
+        let fronts: Vec<String> =
+            self.outputs.iter().filter(|(_, output)| output.offset == 0).map(|(name, _)| name.clone()).collect();
+        if self.insert_path(position, path.clone()) != 0 {
+            return Ok(());
+        }
+        if self.rotation != Rotation::Independent || self.outputs.is_empty() || self.paths.len() == 1 {
+            return self.show_current();
+        }
+
+        // The outputs that were at the front move to it, or the first output
+        // if none was, the others keep their wallpaper.
+        let fronts = if fronts.is_empty() { self.outputs.keys().take(1).cloned().collect() } else { fronts };
+        let mut result = Ok(());
+        for name in fronts {
+            self.outputs.get_mut(&name).unwrap().offset = 0;
+            let set = self.set_wallpaper(path.clone(), Some(&name));
+            if result.is_ok() {
+                result = set;
+            }
+        }
+        result
+    }
+
+    /// Add `dir` to the sources and queue its wallpapers at random positions.
+    pub fn add_directory(&mut self, dir: PathBuf, recursive: bool) -> Result<(), IpcError> {
+        if !dir.is_dir() {
+            return Err(IpcError::InvalidPath { path: dir, message: "not a directory".to_string() });
+        }
+        let source = Source { pattern: dir.to_string_lossy().into_owned(), weight: 1, recursive };
+        let files = source
+            .files(&self.scan_options)
+            .map_err(|e| IpcError::InvalidPath { path: dir.clone(), message: error_message(&e) })?;
+
+        let pattern = source.pattern.clone();
+        self.add_source(source);
+        let mut added = 0;
+        for file in self.validated(files) {
+            if !self.paths.contains(&file) {
+                self.origins.insert(file.clone(), pattern.clone());
+                self.insert_path(None, file);
+                added += 1;
+            }
+        }
+        println!("Added {} wallpapers from {}", added, dir.display());
+        Ok(())
+    }
+
+    /// Drop `path` from the sources and the queue. When it comes from a
+    /// directory source, it is back the next time the sources are read.
+    pub fn remove_wallpaper(&mut self, path: PathBuf) -> Result<(), IpcError> {
+        let sources = self.sources.len();
+        self.sources.retain(|source| Path::new(&source.expanded()) != path);
+        if !self.paths.contains(&path) {
+            return if self.sources.len() < sources { Ok(()) } else { Err(IpcError::PathNotAdded { path }) };
+        }
+        self.remove_path(&path);
+        Ok(())
+    }
+
+    /// Drop the sources in `dir` and the wallpapers under it. Like with
+    /// [`Self::remove_wallpaper`], wallpapers of sources around `dir` are
+    /// back the next time the sources are read.
+    pub fn remove_directory(&mut self, dir: PathBuf) -> Result<(), IpcError> {
+        let sources = self.sources.len();
+        self.sources.retain(|source| !Path::new(&source.expanded()).starts_with(&dir));
+        if !self.paths.iter().any(|path| path.starts_with(&dir)) {
+            return if self.sources.len() < sources { Ok(()) } else { Err(IpcError::PathNotAdded { path: dir }) };
+        }
+        self.remove_path(&dir);
+        Ok(())
+    }
+
+    /// Drop `path`, or everything under it if it was a directory, from the
+    /// wallpapers. Outputs showing one of them move on to the next wallpaper.
+    pub fn remove_path(&mut self, path: &Path) {
+        self.quarantine.retain(|entry, _| !entry.starts_with(path));
+        self.dimensions.retain(|entry, _| !entry.starts_with(path));
+        self.kinds.retain(|entry, _| !entry.starts_with(path));
+        self.origins.retain(|entry, _| !entry.starts_with(path));
+        if !self.paths.iter().any(|entry| entry.starts_with(path)) {
+            return;
+        }
+        println!("Removing {}", path.display());
+
+        let len = self.paths.len();
+        let showing_first = self.paths[0].starts_with(path);
+        let mut stale = Vec::new();
+        for (name, output) in self.outputs.iter_mut() {
+            if self.paths[output.offset % len].starts_with(path) {
+                stale.push(name.clone());
+            }
+            // Keep pointing at the same entry, or the one after it.
+            output.offset -= self.paths[..output.offset.min(len)]
+                .iter()
+                .filter(|entry| entry.starts_with(path))
+                .count();
+        }
+        self.paths.retain(|entry| !entry.starts_with(path));
+
+        if self.paths.is_empty() {
+            return;
+        }
+        for output in self.outputs.values_mut() {
+            output.offset %= self.paths.len();
+        }
+        // Failures are logged and kept in `last_error` by the backend thread.
+        if self.rotation != Rotation::Independent || self.outputs.is_empty() {
+            if showing_first {
+                let _ = self.show_current();
+            }
+            return;
+        }
+        for name in stale {
+            let path = self.paths[self.outputs[&name].offset].clone();
+            let _ = self.set_wallpaper(path, Some(&name));
+        }
+    }
+
+    /// Make `name` the active collection, resuming where it was left. The
+    /// active one is put away with its queue and outputs' positions.
+    pub fn switch_collection(&mut self, name: &str) -> Result<(), IpcError> {
+        if name == self.collection {
+            return Ok(());
+        }
+        let Some(next) = self.collections.get(name) else {
+            return Err(IpcError::UnknownCollection { name: name.to_string() });
+        };
+        let mut scan_options = self.scan_options.clone();
+        scan_options.ordered = next.ordered;
+        let fresh = collect(&next.sources, &scan_options, &mut self.origins)
+            .map_err(|e| IpcError::SourcesFailed { message: error_message(&e) })?;
+        let fresh = self.validated(fresh);
+        let next = self.collections.remove(name).unwrap();
+
+        let len = self.paths.len();
+        let positions = self
+            .outputs
+            .iter()
+            .filter(|_| len > 0)
+            .map(|(output, state)| (output.clone(), self.paths[state.offset % len].clone()))
+            .collect();
+        let previous = Collection {
+            sources: mem::replace(&mut self.sources, next.sources),
+            interval: mem::replace(&mut self.interval, next.interval),
+            ordered: mem::replace(&mut self.scan_options.ordered, next.ordered),
+            saved: Some(Saved { paths: mem::take(&mut self.paths), positions }),
+        };
+        self.collections.insert(mem::replace(&mut self.collection, name.to_string()), previous);
+        println!("Switched to the {} collection", name);
+
+        if let Some(watcher) = &self.watcher {
+            // Only the sources of the new collection are watched.
+            watcher.unwatch_all();
+            for (dir, depth) in self.sources.iter().filter_map(|source| source.watch_root(&self.scan_options)) {
+                watcher.watch(&dir, depth);
+            }
+        }
+        // The timer is still set for the previous interval.
+        if let Some(rearm) = &self.rearm {
+            rearm.ping();
+        }
+
+        match next.saved {
+            None => {
+                self.paths = fresh;
+                let len = self.paths.len().max(1);
+                // Spread the outputs like at startup.
+                for (index, output) in self.outputs.values_mut().enumerate() {
+                    output.offset = if self.rotation == Rotation::Independent { index % len } else { 0 };
+                }
+            }
+            Some(saved) => {
+                // Keep the saved order, without what's gone since.
+                let found: HashSet<&PathBuf> = fresh.iter().collect();
+                self.paths = saved.paths.into_iter().filter(|path| found.contains(path)).collect();
+                for (output, state) in self.outputs.iter_mut() {
+                    state.offset = saved
+                        .positions
+                        .get(output)
+                        .and_then(|saved| self.paths.iter().position(|path| path == saved))
+                        .unwrap_or(0);
+                }
+                for path in fresh {
+                    if !self.paths.contains(&path) {
+                        self.insert_path(None, path);
                     }
                 }
             }
-        });
+        }
 
+        println!("Total wallpapers: {}", self.paths.len());
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+        self.show_current()
+    }
+
+    /// Save the queue, starting with the current wallpaper, as a playlist.
+    pub fn save_playlist(&self, path: PathBuf) -> Result<(), IpcError> {
+        playlist::write(&path, &self.paths).map_err(|e| IpcError::WriteFailed { path, message: error_message(&e) })
+    }
+
+    /// Replace the filters and collect the wallpapers again.
+    pub fn set_filters(&mut self, include: &[String], exclude: &[String]) -> Result<(), IpcError> {
+        let parse = |filters: &[String]| {
+            filters
+                .iter()
+                .map(|filter| {
+                    filter.parse::<Filter>().map_err(|message| IpcError::InvalidFilter { filter: filter.clone(), message })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let (include, exclude) = (parse(include)?, parse(exclude)?);
+        self.scan_options.include = include;
+        self.scan_options.exclude = exclude;
+        self.rebuild()
+    }
+
+    /// Collect the wallpapers from the sources again. Outputs keep their
+    /// wallpaper if it is still there, the others move on to a new one.
+    pub fn rebuild(&mut self) -> Result<(), IpcError> {
+        let paths = collect(&self.sources, &self.scan_options, &mut self.origins)
+            .map_err(|e| IpcError::SourcesFailed { message: error_message(&e) })?;
+        let paths = self.validated(paths);
+        let first = self.paths.first().cloned();
+        self.paths = paths;
+        println!("Total wallpapers: {}", self.paths.len());
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+
+        if self.rotation != Rotation::Independent || self.outputs.is_empty() {
+            return match first.and_then(|first| self.paths.iter().position(|path| path == &first)) {
+                Some(index) => {
+                    self.paths.rotate_left(index);
+                    Ok(())
+                }
+                None => self.show_current(),
+            };
+        }
+
+        let mut stale = Vec::new();
+        for (name, output) in self.outputs.iter_mut() {
+            match output.current.as_ref().and_then(|current| self.paths.iter().position(|path| path == current)) {
+                Some(index) => output.offset = index,
+                None => {
+                    output.offset %= self.paths.len();
+                    stale.push(name.clone());
+                }
+            }
+        }
+        let mut result = Ok(());
+        for name in stale {
+            let path = self.paths[self.outputs[&name].offset].clone();
+            let set = self.set_wallpaper(path, Some(&name));
+            if result.is_ok() {
+                result = set;
+            }
+        }
+        result
+    }
+
+    /// Split `path` into one crop per output and show each on its output.
+    fn show_spanned(&mut self, path: PathBuf) -> Result<(), IpcError> {
+        let outputs: Vec<OutputInfo> = self.outputs.values().map(|o| o.info.clone()).collect();
+        // A video can't be split, its first frame is spanned instead.
+        let source = if is_video(&path) {
+            media::still_frame(&path, MediaKind::Video)
+        } else {
+            Ok(path.clone())
+        };
+        let crops = match source.and_then(|source| span::write_crops(&source, &outputs)) {
+            Ok(crops) => crops,
+            Err(e) => {
+                eprintln!("Failed to span wallpaper: {:?}", e);
+                let err = IpcError::BackendFailed {
+                    backend: "span".to_string(),
+                    path,
+                    message: error_message(&e),
+                };
+                self.last_error = Some(err.clone());
+                return Err(err);
+            }
+        };
+
+        let mut result = Ok(());
+        for (name, crop) in crops {
+            let set = self.show_file(crop, path.clone(), Some(&name));
+            if result.is_ok() {
+                result = set;
+            }
+        }
+        result
+    }
+
+    /// Show `path` on `output`, or on all of them, see [`Self::show_file`].
+    pub fn set_wallpaper(&mut self, path: PathBuf, output: Option<&str>) -> Result<(), IpcError> {
+        self.show_file(path.clone(), path, output)
+    }
+
+    /// `file`, or what to show instead when the backend can't play it.
+    fn playable(&mut self, file: PathBuf, path: &Path) -> Result<PathBuf, IpcError> {
+        let kind = self.kind(&file);
+        let name = self.backend_name;
+        if kind.supported_by(self.capabilities) {
+            return Ok(file);
+        }
+
+        let result = match self.media_fallback {
+            MediaFallback::Skip => Err(IpcError::Unsupported {
+                backend: name.to_string(),
+                message: format!("{} can't show {}", name, file.display()),
+            }),
+            MediaFallback::Still => media::still_frame(&file, kind).map_err(|e| {
+                eprintln!("Failed to extract a still frame: {:?}", e);
+                IpcError::BackendFailed {
+                    backend: "still frame".to_string(),
+                    path: path.to_path_buf(),
+                    message: error_message(&e),
+                }
+            }),
+        };
+        if let Err(e) = &result {
+            self.last_error = Some(e.clone());
+        }
+        result
+    }
+
+    /// Hand `file` to the backend thread, which then updates its preloaded
+    /// images. Once shown, `path` is recorded as the current wallpaper by
+    /// [`Self::report`], and a failure is kept in `last_error`. The next
+    /// wallpaper of the rotation waits for a full interval after this one.
+    fn show_file(&mut self, file: PathBuf, path: PathBuf, output: Option<&str>) -> Result<(), IpcError> {
+        let file = self.playable(file, &path)?;
+        let (paths, shown) = if self.capabilities.preload {
+            let shown = self
+                .outputs
+                .iter()
+                .filter_map(|(name, state)| match output {
+                    Some(output) if output != name => state.shown.clone(),
+                    _ => Some(file.clone()),
+                })
+                .collect();
+            (self.paths.clone(), shown)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let change = Change {
+            file,
+            path: path.clone(),
+            output: output.map(str::to_string),
+            paths,
+            shown,
+            preload: self.preload.clone(),
+        };
+
+        if self.send(Task::Show(change)).is_none() {
+            let err = IpcError::BackendFailed {
+                backend: self.backend_name.to_string(),
+                path,
+                message: "the backend thread isn't running".to_string(),
+            };
+            self.last_error = Some(err.clone());
+            return Err(err);
+        }
+        self.last_update = Some(Instant::now());
+        self.skip_after_manual = true;
         Ok(())
     }
-}
 
-pub fn hyprpaper_preload(path: &str) -> Result<()> {
-    println!("Preload {}", path);
-    Command::new("hyprctl")
-        .arg("hyprpaper")
-        .arg("preload")
-        .arg(path)
-        .output()
-        .map_err(|e| color_eyre::eyre::eyre!("Failed to execute preload command: {:?}", e))?;
-    Ok(())
-}
+    /// Start the thread making the changes, whose outcomes are to be given
+    /// to [`Self::report`] from the returned channel.
+    pub fn spawn_backend_thread(&mut self) -> Channel<Report> {
+        let (jobs, receiver) = mpsc::channel();
+        let (reports, channel) = channel::channel();
+        let backend = Arc::clone(&self.backend);
+        std::thread::spawn(move || backend_thread::run(backend, receiver, reports));
+        self.jobs = Some(jobs);
+        channel
+    }
 
-pub fn hyprpaper_wallpaper(path: &str) -> Result<()> {
-    println!("Wallpaper {}", path);
-    Command::new("hyprctl")
-        .arg("hyprpaper")
-        .arg("wallpaper")
-        .arg(format!(",{}", path))
-        .output()
-        .map_err(|e| color_eyre::eyre::eyre!("Failed to execute wallpaper command: {:?}", e))?;
-    Ok(())
-}
+    /// Queue `task` for the backend thread, returning the id of its job.
+    pub(crate) fn send(&mut self, task: Task) -> Option<u64> {
+        let id = self.next_job;
+        self.jobs.as_ref()?.send(Job { id, task }).ok()?;
+        self.next_job += 1;
+        Some(id)
+    }
 
-pub fn hyprpaper_unload(path: &str) -> Result<()> {
-    println!("Unload {}", path);
-    Command::new("hyprctl")
-        .arg("hyprpaper")
-        .arg("unload")
-        .arg(path)
-        .output()
-        .map_err(|e| color_eyre::eyre::eyre!("Failed to execute unload command: {:?}", e))?;
-    Ok(())
-}
+    /// Go back to the preferred backend in the background, see
+    /// [`Backend::recover`].
+    pub fn recover(&mut self) {
+        self.send(Task::Recover);
+    }
 
-pub fn hyprpaper_get_loaded() -> Result<Vec<String>> {
-    let output = Command::new("hyprctl")
-        .arg("hyprpaper")
-        .arg("listloaded")
-        .output()
-        .map_err(|e| color_eyre::eyre::eyre!("Failed to execute listloaded command: {:?}", e))?;
+    /// Update the transition of the following changes, in the background.
+    pub fn set_transition(&mut self, transition: Transition) -> Result<(), IpcError> {
+        match self.send(Task::SetTransition(transition)) {
+            Some(_) => Ok(()),
+            None => Err(IpcError::Unsupported {
+                backend: self.backend_name.to_string(),
+                message: "the backend thread isn't running".to_string(),
+            }),
+        }
+    }
+
+    /// Record the outcome of a job of the backend thread, and return it.
+    pub fn report(&mut self, report: Report) -> Result<(), IpcError> {
+        self.backend_name = report.name;
+        self.capabilities = report.capabilities;
+        match report.outcome {
+            Outcome::Shown(change, result) => {
+                self.last_error = result.clone().err();
+                if result.is_ok() {
+                    for (name, state) in self.outputs.iter_mut() {
+                        if change.output.as_ref().map_or(true, |output| output == name) {
+                            state.current = Some(change.path.clone());
+                            state.shown = Some(change.file.clone());
+                        }
+                    }
+                }
+                result
+            }
+            Outcome::TransitionSet(result) => result,
+            Outcome::Outputs(Ok(outputs)) => {
+                self.update_outputs(outputs);
+                Ok(())
+            }
+            Outcome::Outputs(Err(e)) => {
+                eprintln!("Failed to list outputs: {:?}", e);
+                Ok(())
+            }
+            Outcome::Recovered => Ok(()),
+        }
+    }
+}
 
-    let stdout = String::from_utf8(output.stdout)
-        .map_err(|e| color_eyre::eyre::eyre!("Invalid UTF-8 sequence: {:?}", e))?;
+pub(crate) fn error_message(e: &color_eyre::Report) -> String {
+    e.chain().map(|e| e.to_string()).collect::<Vec<_>>().join(": ")
+}
 
-    Ok(stdout.lines().map(|line| line.to_string()).collect())
+/// How the outputs move through the wallpapers.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotation {
+    /// Every output shows the same wallpaper
+    Synchronized,
+    /// Each output has its own position in the wallpapers
+    Independent,
+    /// A single wallpaper is split across all outputs
+    Span,
 }
 
-#[derive(clap::ValueEnum, Clone, serde::Serialize)]
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum WallpaperDaemon {
+    /// Pick one from the environment
+    Auto,
     Swww,
     Hyprpaper,
+    Swaybg,
+    /// Run the commands given by `--set-command` and friends
+    Command,
+    /// Draw the wallpaper without an external daemon, using wlr-layer-shell
+    LayerShell,
+    /// Draw the wallpaper on the X11 root window
+    X11,
+    /// Set the GNOME background through gsettings
+    Gnome,
+    /// Set the KDE Plasma wallpaper through plasmashell
+    Kde,
+    /// Set the XFCE backdrop through xfconf
+    Xfce,
+    /// Play videos and animated images with mpvpaper
+    Mpvpaper,
+}
+
+impl WallpaperDaemon {
+    /// Whether mpvpaper can play videos over the wallpapers of this backend,
+    /// that is it draws them on a wlroots background layer.
+    pub fn under_mpvpaper(&self) -> bool {
+        matches!(self, Self::Swww | Self::Hyprpaper | Self::Swaybg | Self::LayerShell | Self::Command)
+    }
+
+    pub fn name(&self) -> String {
+        clap::ValueEnum::to_possible_value(self).map_or_else(String::new, |value| value.get_name().to_string())
+    }
+
+    pub fn backend(&self, options: &BackendOptions) -> Result<Box<dyn Backend>> {
+        Ok(match self {
+            WallpaperDaemon::Auto => return detect_backend()?.daemon.backend(options),
+            WallpaperDaemon::Swww => {
+                Box::new(Swww::new(options.transition.clone().into(), options.start_swww_daemon)?)
+            }
+            WallpaperDaemon::Hyprpaper => Box::new(Hyprpaper::new()?),
+            WallpaperDaemon::Swaybg => Box::new(Swaybg::new(options.mode)),
+            WallpaperDaemon::Command => Box::new(CommandBackend::new(
+                options
+                    .set_command
+                    .as_deref()
+                    .ok_or_else(|| eyre!("--set-command is required with the command backend"))?,
+                options.preload_command.as_deref(),
+                options.unload_command.as_deref(),
+                options.mode,
+            )?),
+            WallpaperDaemon::LayerShell => Box::new(LayerShellBackend::new(options.mode)?),
+            WallpaperDaemon::X11 => Box::new(X11::new(options.mode)?),
+            WallpaperDaemon::Gnome => Box::new(Gnome::new(options.mode)?),
+            WallpaperDaemon::Kde => Box::new(Kde::new(options.mode)),
+            WallpaperDaemon::Xfce => Box::new(Xfce::new(options.mode)),
+            WallpaperDaemon::Mpvpaper => Box::new(Mpvpaper::new(&options.mpv_options, options.mode)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    struct Null;
+
+    impl Backend for Null {
+        fn name(&self) -> &'static str {
+            "null"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::default()
+        }
+
+        fn set(&mut self, _path: &Path, _output: Option<&str>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn manager(paths: &[&str]) -> WallpaperManager {
+        let scan_options = ScanOptions {
+            max_depth: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            shuffle_playlists: false,
+            ordered: true,
+        };
+        let preload = PreloadOptions { preload_ahead: 0, preload_behind: 0, preload_budget: None };
+        let mut manager = WallpaperManager::new(
+            Vec::new(),
+            scan_options,
+            Duration::from_secs(60),
+            Box::new(Null),
+            PathBuf::new(),
+            Rotation::Synchronized,
+            preload,
+        )
+        .unwrap();
+        manager.paths = paths.iter().map(PathBuf::from).collect();
+        manager.spawn_backend_thread();
+        manager
+    }
+
+    /// Run the rotation timer once, which must not panic.
+    fn rotate(manager: &mut WallpaperManager) {
+        crate::rotate(Instant::now(), &mut (), manager);
+    }
+
+    #[test]
+    fn next_with_an_empty_queue_changes_nothing() {
+        let mut manager = manager(&[]);
+        manager.step(None, true).unwrap();
+        manager.go_to(PathBuf::from("/a.png"), None).unwrap_err();
+        assert_eq!(manager.next_job, 0);
+        assert!(!manager.skip_after_manual);
+        rotate(&mut manager);
+        rotate(&mut manager);
+    }
+
+    #[test]
+    fn next_with_nothing_fitting_changes_nothing() {
+        let mut manager = manager(&["/a.png", "/b.png"]);
+        manager.dimensions = manager.paths.iter().map(|path| (path.clone(), (1, 1))).collect();
+        manager.resolution_rules = ResolutionRules { min_width: Some(100), min_height: Some(100), ..Default::default() };
+        manager.step(None, true).unwrap();
+        manager.show_current().unwrap();
+        assert_eq!(manager.next_job, 0);
+        assert!(!manager.skip_after_manual);
+        rotate(&mut manager);
+    }
+
+    #[test]
+    fn manual_changes_give_the_next_wallpaper_a_full_interval() {
+        let mut manager = manager(&["/a.png", "/b.png"]);
+        manager.step(None, true).unwrap();
+        assert_eq!(manager.next_job, 1);
+        assert!(manager.skip_after_manual && manager.last_update.is_some());
+        rotate(&mut manager);
+        assert!(!manager.skip_after_manual);
+        assert_eq!(manager.next_job, 1);
+
+        // The rotation's own changes don't skip the next one.
+        rotate(&mut manager);
+        assert_eq!(manager.next_job, 2);
+        assert!(!manager.skip_after_manual);
+    }
+
+    #[test]
+    fn rewritten_bad_wallpapers_leave_the_queue() {
+        let dir = env::temp_dir().join(format!("wallpaper-manager-test-{}-rewritten", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (good, bad) = (dir.join("good.png"), dir.join("bad.png"));
+        for path in [&good, &bad] {
+            image::RgbImage::new(2, 2).save_with_format(path, image::ImageFormat::Png).unwrap();
+        }
+        let mut manager = manager(&[]);
+        manager.sources = vec![Source { pattern: dir.to_string_lossy().into_owned(), weight: 1, recursive: true }];
+        manager.paths = manager.validated(vec![good.clone(), bad.clone()]);
+        assert_eq!(manager.paths.len(), 2);
+
+        fs::write(&bad, "not an image").unwrap();
+        manager.add_found(&bad);
+        manager.add_found(&good);
+        assert_eq!(manager.paths, [good]);
+        assert!(manager.quarantine.contains_key(&bad));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_timestamps_dont_panic() {
+        let mut manager = manager(&[]);
+        manager.skip_after_manual = true;
+        rotate(&mut manager);
+        manager.last_pause = Some(Instant::now());
+        rotate(&mut manager);
+    }
 }
@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::fd::{AsFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use smithay_client_toolkit::reexports::calloop::generic::Generic;
+use smithay_client_toolkit::reexports::calloop::{
+    self, EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+};
+
+/// A change to the files under the watched directories.
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// A file or directory was created, written or moved in.
+    Added(PathBuf),
+    /// A file or directory was deleted or moved out.
+    Removed(PathBuf),
+}
+
+/// Watches directory trees with inotify, including the subdirectories
+/// created later.
+pub struct WatchSource {
+    inotify: Inotify,
+    /// A copy of the inotify descriptor, polled by the event loop.
+    fd: Generic<OwnedFd>,
+    watcher: Watcher,
+}
+
+impl WatchSource {
+    pub fn new() -> io::Result<Self> {
+        let inotify = Inotify::init()?;
+        let fd = inotify.as_fd().try_clone_to_owned()?;
+        let watcher = Watcher(Rc::new(RefCell::new(Watched { watches: inotify.watches(), dirs: HashMap::new() })));
+
+        Ok(Self {
+            inotify,
+            fd: Generic::new(fd, Interest::READ, Mode::Level),
+            watcher,
+        })
+    }
+
+    /// A handle adding directories to this source, usable once it's in the
+    /// event loop.
+    pub fn watcher(&self) -> Watcher {
+        self.watcher.clone()
+    }
+}
+
+/// Adds directories to the watch of a [`WatchSource`].
+#[derive(Clone)]
+pub struct Watcher(Rc<RefCell<Watched>>);
+
+struct Watched {
+    watches: Watches,
+    /// Watched directory per watch, with how many more levels of
+    /// subdirectories to watch below it, `None` for all of them.
+    dirs: HashMap<WatchDescriptor, (PathBuf, Option<usize>)>,
+}
+
+impl Watcher {
+    /// Watch `dir` and its subdirectories up to `depth` levels down. Hidden
+    /// directories are left out, like when scanning.
+    pub fn watch(&self, dir: &Path, depth: Option<usize>) {
+        self.0.borrow_mut().watch_tree(dir, depth, &mut HashSet::new());
+    }
+
+    /// Stop watching every directory.
+    pub fn unwatch_all(&self) {
+        let Watched { watches, dirs } = &mut *self.0.borrow_mut();
+        for (wd, _) in dirs.drain() {
+            // Fails if the directory was deleted meanwhile, which is fine.
+            let _ = watches.remove(wd);
+        }
+    }
+}
+
+impl Watched {
+    fn watch_tree(&mut self, dir: &Path, depth: Option<usize>, visited: &mut HashSet<PathBuf>) {
+        // `dir` is empty for the parent of a relative file name.
+        let target = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        // Symlinks may lead back to a directory already watched.
+        let Ok(canonical) = fs::canonicalize(target) else {
+            return;
+        };
+        if !visited.insert(canonical) {
+            return;
+        }
+
+        let mask = WatchMask::CREATE
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_TO
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM;
+        match self.watches.add(target, mask) {
+            Ok(wd) => {
+                self.dirs.insert(wd, (dir.to_path_buf(), depth));
+            }
+            Err(e) => {
+                eprintln!("Failed to watch {}: {}", target.display(), e);
+                return;
+            }
+        }
+
+        let Some(depth) = depth.map_or(Some(None), |depth| depth.checked_sub(1).map(Some)) else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir(target) else {
+            return;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = dir.join(entry.file_name());
+            if path.is_dir() {
+                self.watch_tree(&path, depth, visited);
+            }
+        }
+    }
+}
+
+impl EventSource for WatchSource {
+    type Error = io::Error;
+    type Event = WatchEvent;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> io::Result<PostAction>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let mut events = Vec::new();
+        self.fd.process_events(readiness, token, |_, _| {
+            let mut buffer = [0; 4096];
+            loop {
+                match self.inotify.read_events(&mut buffer) {
+                    Ok(read) => events.extend(read.map(|event| event.to_owned())),
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(PostAction::Continue)
+        })?;
+
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                eprintln!("Too many changes in the wallpaper directories, some were missed");
+                continue;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                // The directory was deleted.
+                self.watcher.0.borrow_mut().dirs.remove(&event.wd);
+                continue;
+            }
+            let dir = self.watcher.0.borrow().dirs.get(&event.wd).cloned();
+            let (Some((dir, depth)), Some(name)) = (dir, event.name) else {
+                continue;
+            };
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = dir.join(name);
+
+            if event.mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+                callback(WatchEvent::Removed(path), &mut ());
+            } else if event.mask.contains(EventMask::ISDIR) {
+                if depth == Some(0) {
+                    continue;
+                }
+                self.watcher.watch(&path, depth.map(|depth| depth - 1));
+                callback(WatchEvent::Added(path), &mut ());
+            } else if event.mask.contains(EventMask::CREATE)
+                && !path.is_symlink()
+                && fs::metadata(&path).map_or(true, |metadata| metadata.len() == 0)
+            {
+                // Still empty, CLOSE_WRITE follows once it's written. Hard
+                // links come with their contents and only get CREATE.
+                continue;
+            } else {
+                callback(WatchEvent::Added(path), &mut ());
+            }
+        }
+
+        Ok(PostAction::Continue)
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.fd.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.fd.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.fd.unregister(poll)
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
@@ -8,12 +9,26 @@ pub enum IpcMessage {
     StopDaemon,
     PausePlay,
     ResumePlay,
-    NextWallpaper,
-    PreviousWallpaper,
+    NextWallpaper { output: Option<String> },
+    PreviousWallpaper { output: Option<String> },
     MoveWallpaperToIndex { path: PathBuf, index: usize },
-    GoToWallpaper { path: PathBuf },
+    GoToWallpaper { path: PathBuf, output: Option<String> },
     AllWallpapers,
     CurrentInterval,
+    CurrentWallpapers,
+    LastError,
+    SetTransition { transition: Transition },
+    Status,
+    SetFilters { include: Vec<String>, exclude: Vec<String> },
+    AddWallpaper { path: PathBuf, position: Option<usize> },
+    RemoveWallpaper { path: PathBuf },
+    AddDirectory { dir: PathBuf, recursive: bool },
+    RemoveDirectory { dir: PathBuf },
+    SavePlaylist { path: PathBuf },
+    SwitchCollection { name: String },
+    InvalidWallpapers,
+    SetResolutionRules { rules: ResolutionRules },
+    ResolutionRules,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -21,11 +36,86 @@ pub enum IpcResponse {
     Ok,
     AllWallpapers { entries: Vec<PathBuf> },
     CurrentInterval { is_paused: bool, interval: u128, elapsed: u128 },
+    CurrentWallpapers { entries: BTreeMap<String, PathBuf> },
+    LastError { error: Option<IpcError> },
+    InvalidWallpapers { entries: BTreeMap<PathBuf, String> },
+    ResolutionRules { rules: ResolutionRules },
+    Status {
+        backend: String,
+        chain: Vec<String>,
+        reason: Option<String>,
+        is_paused: bool,
+        wallpapers: usize,
+        collection: String,
+        collections: Vec<String>,
+    },
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum IpcError {
-    PathNotAdded { path: PathBuf }
+    PathNotAdded { path: PathBuf },
+    BackendFailed { backend: String, path: PathBuf, message: String },
+    UnknownOutput { output: String },
+    Unsupported { backend: String, message: String },
+    InvalidFilter { filter: String, message: String },
+    SourcesFailed { message: String },
+    InvalidPath { path: PathBuf, message: String },
+    WriteFailed { path: PathBuf, message: String },
+    UnknownCollection { name: String },
+    InvalidRules { message: String },
+}
+
+/// Rules wallpapers must follow to be shown, `None` fields don't apply.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct ResolutionRules {
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    /// Largest difference between the aspect ratios of a wallpaper and its
+    /// output, as a fraction of the output's.
+    pub aspect_tolerance: Option<f32>,
+    pub match_orientation: bool,
+}
+
+/// Transition settings, `None` fields keep their current value.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Transition {
+    /// Clear the current settings before applying these, so the fields not
+    /// given go back to the backend defaults.
+    #[serde(default)]
+    pub reset: bool,
+    pub kind: Option<String>,
+    pub step: Option<u8>,
+    pub fps: Option<u16>,
+    pub duration: Option<f32>,
+    pub angle: Option<f32>,
+    pub position: Option<String>,
+}
+
+impl ResolutionRules {
+    /// Check the rules can be applied, the tolerance can't be negative or NaN.
+    pub fn validate(&self) -> Result<(), IpcError> {
+        match self.aspect_tolerance {
+            Some(tolerance) if tolerance.is_nan() || tolerance < 0.0 => Err(IpcError::InvalidRules {
+                message: format!("the aspect tolerance must be 0 or more, not {}", tolerance),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Transition {
+    /// Apply `changes` on top of these settings.
+    pub fn merge(&mut self, changes: &Transition) {
+        if changes.reset {
+            *self = Transition::default();
+        }
+        self.kind = changes.kind.clone().or(self.kind.take());
+        self.step = changes.step.or(self.step);
+        self.fps = changes.fps.or(self.fps);
+        self.duration = changes.duration.or(self.duration);
+        self.angle = changes.angle.or(self.angle);
+        self.position = changes.position.clone().or(self.position.take());
+    }
 }
 
 pub fn socket_path() -> Result<PathBuf, BaseDirectoriesError> {